@@ -1,9 +1,586 @@
-use lib_rust_1pass::make_session;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand};
+use lib_rust_1pass::{
+    default_install_config, find_local_version_file, get_or_create_in, get_or_install,
+    global_version_file, init_script, install_version, list_installed, list_remote_versions_marked,
+    local_accounts, parse_version_pin, prune, resolve_version, selected_binary, shims_dir,
+    sign_in_with, uninstall, verify_installation, write_shim, write_version_file, AskpassPassword,
+    EnvPassword, FdPassword, InstallConfig, Installation, LocalVersion, PasswordProvider, Platform,
+    ReleaseNoteUrl, ReqwestTransport, Session, Shell, Transport, TtyPrompt, LOCAL_VERSION_FILENAME,
+    SERVICE_ACCOUNT_TOKEN_ENV,
+};
+use semver::{Version, VersionReq};
+use serde_json::json;
+
+/// the command failed
+const EXIT_FAILURE: u8 = 1;
+/// doctor found problems
+const EXIT_UNHEALTHY: u8 = 3;
+
+/// manage the 1password cli (op) binaries
+#[derive(Parser)]
+#[clap(name = "openv")]
+struct Cli {
+    /// print the output (and errors) as json
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// where openv keeps the binaries; $OPENV_ROOT, ~/.op_cli (if it exists) or
+    /// ~/.local/share/openv by default
+    #[clap(long, global = true)]
+    root: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// where the master password comes from; the terminal by default
+#[derive(Args)]
+struct PasswordArgs {
+    /// read the master password from the env var, e.g. OPENV_PASSWORD
+    #[clap(long, value_name = "VAR", conflicts_with_all = &["password-stdin", "password-fd", "askpass"])]
+    password_env: Option<String>,
+    /// read the first line of stdin
+    #[clap(long, conflicts_with_all = &["password-fd", "askpass"])]
+    password_stdin: bool,
+    /// read the first line of the file descriptor
+    #[clap(long, value_name = "FD", conflicts_with = "askpass")]
+    password_fd: Option<i32>,
+    /// run the program (e.g. ssh-askpass) and read the first line it prints
+    #[clap(long, value_name = "PROGRAM")]
+    askpass: Option<PathBuf>,
+}
+
+impl PasswordArgs {
+    fn provider(&self) -> anyhow::Result<Arc<dyn PasswordProvider>> {
+        Ok(if let Some(var) = &self.password_env {
+            Arc::new(EnvPassword::new(var))
+        } else if self.password_stdin {
+            Arc::new(FdPassword::stdin())
+        } else if let Some(fd) = self.password_fd {
+            Arc::new(FdPassword::open_fd(fd)?)
+        } else if let Some(program) = &self.askpass {
+            Arc::new(AskpassPassword::new(program))
+        } else {
+            Arc::new(TtyPrompt)
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// install a version (e.g. 2, 1.12 or ~1.11); the latest release by default
+    Install {
+        version: Option<String>,
+        /// install the binary built for another platform, e.g. linux_arm64
+        #[clap(long)]
+        platform: Option<Platform>,
+        /// install into this directory instead of the openv root
+        #[clap(long)]
+        dir: Option<PathBuf>,
+    },
+    /// remove an installed version
+    Uninstall { version: Version },
+    /// list the installed versions, newest first; * marks the selected one
+    List,
+    /// list the released versions; * marks the installed ones, the newest one for this
+    /// platform is tagged (newest)
+    ListRemote {
+        /// the 1.x releases instead of the 2.x ones
+        #[clap(long)]
+        v1: bool,
+    },
+    /// show the path of the selected op binary
+    Which,
+    /// install the version if needed and pin it to the current directory (or globally)
+    Use {
+        version: String,
+        #[clap(long)]
+        global: bool,
+    },
+    /// remove all but the newest versions; the selected version is always kept
+    Prune {
+        /// how many of the newest versions to keep
+        #[clap(long, default_value = "1")]
+        keep: usize,
+        /// never remove the versions matching this requirement (repeatable)
+        #[clap(long = "pin")]
+        pinned: Vec<VersionReq>,
+    },
+    /// set (or show) the version of the project in the current directory (.op-version)
+    Local { version: Option<String> },
+    /// set (or show) the global version
+    Global { version: Option<String> },
+    /// show the selected version and where it is set
+    Version,
+    /// list the accounts configured on this device
+    Accounts,
+    /// sign in to an account; print the session as a shell export
+    Signin {
+        account: String,
+        #[clap(flatten)]
+        password: PasswordArgs,
+    },
+    /// print the fields of an item
+    Get {
+        item: String,
+        /// comma separated, e.g. username,password
+        #[clap(long, use_value_delimiter = true, required = true)]
+        fields: Vec<String>,
+        /// the account shorthand to sign in to; $OP_SERVICE_ACCOUNT_TOKEN (2.x) is used
+        /// without signing in if not given
+        #[clap(long)]
+        account: Option<String>,
+        #[clap(flatten)]
+        password: PasswordArgs,
+    },
+    /// check the installation and the environment
+    Doctor,
+    /// write the op shim; with `-`, print the shell integration: eval "$(openv init -)"
+    Init {
+        #[clap(value_parser = ["-"])]
+        print: Option<String>,
+        /// bash, zsh or fish; guessed from $SHELL by default
+        #[clap(long)]
+        shell: Option<Shell>,
+    },
+    /// run the selected op version with the arguments after --, e.g. exec -- --json item list
+    #[clap(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        disable_help_flag = true
+    )]
+    Exec {
+        #[clap(allow_hyphen_values = true, value_parser)]
+        args: Vec<OsString>,
+    },
+}
+
+#[cfg(target_family = "unix")]
+fn exec(bin: &str, args: Vec<OsString>) -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+    // only returns on failure
+    Err(process::Command::new(bin).args(args).exec().into())
+}
+
+#[cfg(target_family = "windows")]
+fn exec(bin: &str, args: Vec<OsString>) -> anyhow::Result<()> {
+    let status = process::Command::new(bin).args(args).status()?;
+    process::exit(status.code().unwrap_or(1));
+}
+
+/// print the json value, or the text
+fn emit(cli: &Cli, value: serde_json::Value, text: impl FnOnce()) {
+    if cli.json {
+        println!("{}", value);
+    } else {
+        text();
+    }
+}
+
+fn local_version_json(lv: &LocalVersion) -> serde_json::Value {
+    json!({
+        "version": lv.version.to_string(),
+        "platform": lv.platform.to_string(),
+        "path": lv.path,
+        "manifest": lv.manifest,
+    })
+}
+
+/// installs into dirname; the config (mirror, release notes cache) comes from the openv root
+async fn install(
+    home_dir: &str,
+    dirname: &Path,
+    version: Option<&str>,
+    platform: Option<Platform>,
+) -> anyhow::Result<Installation> {
+    let transport = ReqwestTransport::default();
+    let config = InstallConfig {
+        platform,
+        ..default_install_config(home_dir)?
+    };
+    match version {
+        Some(v) => {
+            let req = parse_version_pin(v)?;
+            let url = ReleaseNoteUrl::for_requirement(&req);
+            install_version(dirname, url, &req, &transport, &config).await
+        }
+        None => get_or_install(dirname, ReleaseNoteUrl::V2, &transport, &config).await,
+    }
+}
+
+#[derive(PartialEq)]
+enum Health {
+    Good,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    health: Health,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, health: Health, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            health,
+            detail: detail.into(),
+        }
+    }
+}
+
+async fn doctor(dirname: &Path, cwd: &Path) -> Vec<Check> {
+    use Health::*;
+    let mut checks = vec![Check::new("home", Good, dirname.to_string_lossy())];
+    checks.push(match list_installed(dirname).await {
+        Ok(xs) if xs.is_empty() => Check::new("installed", Fail, "nothing is installed"),
+        Ok(xs) => Check::new("installed", Good, format!("{} versions", xs.len())),
+        Err(e) => Check::new("installed", Fail, e.to_string()),
+    });
+    match resolve_version(dirname, cwd).await {
+        Ok(rv) => {
+            let lv = &rv.local_version;
+            checks.push(Check::new(
+                "selected",
+                Good,
+                format!("{} (set by {})", lv.version, rv.source),
+            ));
+            checks.push(match verify_installation(lv) {
+                Ok(_) => Check::new("integrity", Good, "matches the install manifest"),
+                Err(e) => match lv.manifest {
+                    // installed by an earlier release of openv
+                    None => Check::new("integrity", Warn, e.to_string()),
+                    Some(_) => Check::new("integrity", Fail, e.to_string()),
+                },
+            });
+            checks.push(
+                match process::Command::new(&lv.path).arg("--version").output() {
+                    Ok(out) if out.status.success() => Check::new(
+                        "runnable",
+                        Good,
+                        String::from_utf8_lossy(&out.stdout).trim(),
+                    ),
+                    Ok(out) => Check::new(
+                        "runnable",
+                        Fail,
+                        String::from_utf8_lossy(&out.stderr).trim(),
+                    ),
+                    Err(e) => Check::new("runnable", Fail, e.to_string()),
+                },
+            );
+        }
+        Err(e) => checks.push(Check::new("selected", Fail, e.to_string())),
+    }
+    let shims = shims_dir(dirname);
+    let on_path = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).any(|d| d == shims))
+        .unwrap_or(false);
+    checks.push(if on_path {
+        Check::new("shims", Good, shims.to_string_lossy())
+    } else {
+        Check::new("shims", Warn, "not on PATH; see `openv init`")
+    });
+    checks.push(
+        match process::Command::new("gpg").arg("--version").output() {
+            Ok(out) if out.status.success() => Check::new("gpg", Good, "found"),
+            _ => Check::new("gpg", Warn, "not found; signatures can't be verified"),
+        },
+    );
+    if InstallConfig::offline_from_env() {
+        checks.push(Check::new("release notes", Warn, "offline"));
+    } else {
+        let url = ReleaseNoteUrl::V2.to_string();
+        checks.push(match ReqwestTransport::default().get_text(&url).await {
+            Ok(_) => Check::new("release notes", Good, url),
+            Err(e) => Check::new("release notes", Warn, format!("{}: {}", url, e)),
+        });
+    }
+    checks
+}
+
+async fn run(cli: &Cli) -> anyhow::Result<ExitCode> {
+    let home_dir = get_or_create_in(cli.root.as_deref()).await?;
+    let dirname = Path::new(&home_dir);
+    let cwd = std::env::current_dir()?;
+    match &cli.command {
+        Command::Install {
+            version,
+            platform,
+            dir,
+        } => {
+            let dirname = match dir {
+                Some(d) => {
+                    tokio::fs::create_dir_all(d).await?;
+                    d.as_path()
+                }
+                None => dirname,
+            };
+            let inst = install(&home_dir, dirname, version.as_deref(), *platform).await?;
+            let lv = &inst.local_version;
+            emit(cli, local_version_json(lv), || {
+                println!("{} {}", lv.version, lv.path)
+            });
+        }
+        Command::Uninstall { version } => {
+            let lv = uninstall(dirname, version).await?;
+            emit(cli, local_version_json(&lv), || {
+                println!("removed {}", lv.path)
+            });
+        }
+        Command::List => {
+            let selected = resolve_version(dirname, &cwd).await.ok();
+            let is_selected = |lv: &LocalVersion| {
+                selected
+                    .as_ref()
+                    .is_some_and(|rv| rv.local_version.version == lv.version)
+            };
+            let xs = list_installed(dirname).await?;
+            let value = xs
+                .iter()
+                .map(|lv| {
+                    let mut v = local_version_json(lv);
+                    v["selected"] = json!(is_selected(lv));
+                    v
+                })
+                .collect();
+            emit(cli, serde_json::Value::Array(value), || {
+                for lv in &xs {
+                    let mark = if is_selected(lv) { "*" } else { " " };
+                    let channel = match &lv.manifest {
+                        Some(m) => m.channel.to_string(),
+                        None => "-".to_string(),
+                    };
+                    println!("{} {}\t{}\t{}", mark, lv.version, channel, lv.path);
+                }
+            });
+        }
+        Command::ListRemote { v1 } => {
+            let url = if *v1 {
+                ReleaseNoteUrl::V1
+            } else {
+                ReleaseNoteUrl::V2
+            };
+            let config = default_install_config(&home_dir)?;
+            let transport = ReqwestTransport::default();
+            let versions = list_remote_versions_marked(dirname, url, &transport, &config).await?;
+            let value = versions
+                .iter()
+                .map(|rv| {
+                    json!({
+                        "version": rv.version.to_string(),
+                        "platforms": rv.platforms.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+                        "installed": rv.installed,
+                        "newest": rv.newest,
+                    })
+                })
+                .collect();
+            emit(cli, serde_json::Value::Array(value), || {
+                for rv in &versions {
+                    let mark = if rv.installed { "*" } else { " " };
+                    let newest = if rv.newest { " (newest)" } else { "" };
+                    let platforms: Vec<_> = rv.platforms.iter().map(|p| p.to_string()).collect();
+                    println!("{} {}{}\t{}", mark, rv.version, newest, platforms.join(" "));
+                }
+            });
+        }
+        Command::Which => {
+            let rv = resolve_version(dirname, &cwd).await?;
+            let lv = &rv.local_version;
+            emit(cli, local_version_json(lv), || println!("{}", lv.path));
+        }
+        Command::Use { version, global } => {
+            let lv = install(&home_dir, dirname, Some(version), None)
+                .await?
+                .local_version;
+            let filename = if *global {
+                global_version_file(dirname)
+            } else {
+                cwd.join(LOCAL_VERSION_FILENAME)
+            };
+            write_version_file(&filename, version).await?;
+            let mut value = local_version_json(&lv);
+            value["pinned_in"] = json!(filename);
+            emit(cli, value, || {
+                println!("using {} ({})", lv.version, filename.display())
+            });
+        }
+        Command::Prune { keep, pinned } => {
+            let removed = prune(dirname, &cwd, *keep, pinned).await?;
+            let value = removed.iter().map(local_version_json).collect();
+            emit(cli, serde_json::Value::Array(value), || {
+                for lv in &removed {
+                    println!("removed {}", lv.path);
+                }
+            });
+        }
+        Command::Local { version } => match version {
+            Some(v) => write_version_file(&cwd.join(LOCAL_VERSION_FILENAME), v).await?,
+            None => {
+                let p = find_local_version_file(&cwd)
+                    .ok_or_else(|| anyhow::anyhow!("no local version is set."))?;
+                let text = tokio::fs::read_to_string(&p).await?;
+                emit(cli, json!({ "version": text.trim(), "file": p }), || {
+                    print!("{}", text)
+                });
+            }
+        },
+        Command::Global { version } => {
+            let p = global_version_file(dirname);
+            match version {
+                Some(v) => write_version_file(&p, v).await?,
+                None if p.is_file() => {
+                    let text = tokio::fs::read_to_string(&p).await?;
+                    emit(cli, json!({ "version": text.trim(), "file": p }), || {
+                        print!("{}", text)
+                    });
+                }
+                None => return Err(anyhow::anyhow!("no global version is set.")),
+            }
+        }
+        Command::Version => {
+            let rv = resolve_version(dirname, &cwd).await?;
+            let mut value = local_version_json(&rv.local_version);
+            value["source"] = json!(rv.source.to_string());
+            emit(cli, value, || {
+                println!("{} (set by {})", rv.local_version.version, rv.source)
+            });
+        }
+        Command::Accounts => {
+            let lv = selected_binary(&home_dir, &cwd).await?;
+            let accounts = local_accounts(&lv).await?;
+            let value = accounts
+                .iter()
+                .map(|a| json!({ "shorthand": a.shorthand, "email": a.email, "url": a.op_url }))
+                .collect();
+            emit(cli, serde_json::Value::Array(value), || {
+                for a in &accounts {
+                    println!("{}\t{}\t{}", a.shorthand, a.email, a.op_url);
+                }
+            });
+        }
+        Command::Signin { account, password } => {
+            let lv = selected_binary(&home_dir, &cwd).await?;
+            let (key, value) = sign_in_with(&lv, account, password.provider()?)
+                .await?
+                .env();
+            emit(cli, json!({ "key": key, "value": value }), || {
+                println!("export {}=\"{}\"", key, value)
+            });
+        }
+        Command::Get {
+            item,
+            fields,
+            account,
+            password,
+        } => {
+            let lv = selected_binary(&home_dir, &cwd).await?;
+            let sess = match account {
+                Some(account) => sign_in_with(&lv, account, password.provider()?).await?,
+                None => {
+                    let token = std::env::var(SERVICE_ACCOUNT_TOKEN_ENV).map_err(|_| {
+                        anyhow::anyhow!(
+                            "--account is required unless {} is set.",
+                            SERVICE_ACCOUNT_TOKEN_ENV
+                        )
+                    })?;
+                    Session::from_service_account_token(&lv, &token)?
+                }
+            };
+            let names: Vec<&str> = fields.iter().map(String::as_str).collect();
+            let values = sess.item_fields(item, &names).await?;
+            let value = fields
+                .iter()
+                .cloned()
+                .zip(values.iter().cloned().map(serde_json::Value::String))
+                .collect();
+            emit(cli, serde_json::Value::Object(value), || {
+                for v in &values {
+                    println!("{}", v);
+                }
+            });
+        }
+        Command::Doctor => {
+            let checks = doctor(dirname, &cwd).await;
+            let health = |c: &Check| match c.health {
+                Health::Good => "ok",
+                Health::Warn => "warn",
+                Health::Fail => "fail",
+            };
+            let value = checks
+                .iter()
+                .map(|c| json!({ "check": c.name, "health": health(c), "detail": c.detail }))
+                .collect();
+            emit(cli, serde_json::Value::Array(value), || {
+                for c in &checks {
+                    println!("[{}] {}: {}", health(c), c.name, c.detail);
+                }
+            });
+            if checks.iter().any(|c| c.health == Health::Fail) {
+                return Ok(ExitCode::from(EXIT_UNHEALTHY));
+            }
+        }
+        Command::Init { print, shell } => {
+            let shell = shell.unwrap_or_else(Shell::from_env);
+            let shim = write_shim(dirname, &std::env::current_exe()?).await?;
+            if print.is_some() {
+                print!("{}", init_script(shell, &shims_dir(dirname)));
+            } else {
+                println!("wrote {}", shim.display());
+                println!("# add this to the config of {}:", shell);
+                match shell {
+                    Shell::Fish => println!("openv init - | source"),
+                    _ => println!("eval \"$(openv init -)\""),
+                }
+            }
+        }
+        Command::Exec { args } => {
+            let rv = resolve_version(dirname, &cwd).await?;
+            exec(&rv.local_version.path, args.clone())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let sess = make_session("immu").await?;
-    let values = sess.item_fields("Wei Ning", &["first name", "last name"])?;
-    println!("{:?}", values);
-    Ok(())
+async fn main() -> ExitCode {
+    // clap itself exits with 2 on usage errors
+    let cli = Cli::parse();
+    match run(&cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            if cli.json {
+                eprintln!("{}", json!({ "error": format!("{:#}", e) }));
+            } else {
+                eprintln!("error: {:#}", e);
+            }
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exec_forwards_the_arguments_after_dashes() {
+        let cli =
+            Cli::try_parse_from(["openv", "exec", "--", "--json", "item", "list", "--"]).unwrap();
+        assert!(!cli.json);
+        match cli.command {
+            Command::Exec { args } => assert_eq!(
+                vec!["--json", "item", "list", "--"],
+                args.iter().map(|x| x.to_str().unwrap()).collect::<Vec<_>>()
+            ),
+            _ => panic!("not exec"),
+        }
+    }
 }
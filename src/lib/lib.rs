@@ -1,23 +1,180 @@
 mod openv;
 mod session;
 
-use openv::*;
+use std::sync::Arc;
+
 use session::*;
 
+pub use openv::{
+    fetch_release_history, get_or_install, install_version, parse_release_history, InstallConfig,
+    InstallError, Installation, Mirror, Phase, Platform, Progress, ProgressEvent, ProgressObserver,
+    Release, ReleaseEntry, ReleaseNoteUrl, SignatureCheck, VerificationError,
+    ONE_PASSWORD_PUBLIC_KEY, ONE_PASSWORD_SIGNING_KEY,
+};
+pub use openv::{
+    find_local_version_file, global_version_file, parse_version_pin, resolve_version,
+    selected_version, write_version_file, ResolvedVersion, VersionSource, GLOBAL_VERSION_FILENAME,
+    LOCAL_VERSION_FILENAME, VERSION_ENV,
+};
+pub use openv::{
+    get_or_create, get_or_create_in, list_installed, prune, root_dir, uninstall,
+    verify_installation, Channel, InstallManifest, LocalVersion, MANIFEST_FILENAME, ROOT_ENV,
+    VERSIONS_DIRNAME,
+};
+pub use openv::{init_script, shims_dir, write_shim, Shell, SHIMS_DIRNAME};
+pub use openv::{
+    list_remote_versions, list_remote_versions_marked, mark_remote_versions, RemoteVersion,
+};
+pub use openv::{
+    Body, Conditional, ContentKind, FsTransport, MemoryTransport, ReqwestTransport, Transport,
+    TransportError, TransportResponse, Validators,
+};
+pub use openv::{ReleaseNotesCache, DEFAULT_RELEASE_NOTES_TTL};
+pub use session::{
+    Account, Field, FieldPurpose, FieldType, Item, ItemUrl, OpError, Section, Session, SessionCode,
+    Vault, SERVICE_ACCOUNT_TOKEN_ENV,
+};
+pub use session::{
+    AskpassPassword, EnvPassword, FdPassword, FnPassword, PasswordProvider, TtyPrompt, PASSWORD_ENV,
+};
+
 // prelude
 
+pub fn default_install_config(home_dir: &str) -> anyhow::Result<InstallConfig> {
+    Ok(InstallConfig {
+        mirror: Mirror::load(std::path::Path::new(home_dir))?,
+        release_notes_cache: Some(ReleaseNotesCache::new(
+            std::path::Path::new(home_dir),
+            DEFAULT_RELEASE_NOTES_TTL,
+        )),
+        offline: InstallConfig::offline_from_env(),
+        ..InstallConfig::default()
+    })
+}
+
+/// the binary selected for the directory (see resolve_version); the latest release is
+/// installed if there is none at all
+pub async fn selected_binary(
+    home_dir: &str,
+    cwd: &std::path::Path,
+) -> anyhow::Result<LocalVersion> {
+    let dirname = std::path::Path::new(home_dir);
+    match resolve_version(dirname, cwd).await {
+        Ok(rv) => Ok(rv.local_version),
+        Err(e) => {
+            if !list_installed(dirname).await?.is_empty() {
+                return Err(e);
+            }
+            let inst = get_or_install(
+                dirname,
+                ReleaseNoteUrl::V2,
+                &ReqwestTransport::default(),
+                &default_install_config(home_dir)?,
+            )
+            .await?;
+            Ok(inst.local_version)
+        }
+    }
+}
+
+fn session_config(
+    local_version: &LocalVersion,
+    shorthand: &str,
+    password: Arc<dyn PasswordProvider>,
+) -> SessionConfig {
+    SessionConfig {
+        bin_filename: local_version.path.clone(),
+        shorthand: shorthand.to_string(),
+        password,
+    }
+}
+
+/// the accounts configured in the host system
+pub async fn local_accounts(local_version: &LocalVersion) -> Result<Vec<Account>, OpError> {
+    let sess_conf = session_config(local_version, "", Arc::new(TtyPrompt));
+    match ReleaseNoteUrl::for_version(&local_version.version) {
+        ReleaseNoteUrl::V1 => local_accounts_v1(&sess_conf).await,
+        ReleaseNoteUrl::V2 => local_accounts_v2(&sess_conf).await,
+    }
+}
+
+/// sign in to the account (shorthand) with the binary; prompt for the master password
+pub async fn sign_in(local_version: &LocalVersion, shorthand: &str) -> Result<Session, OpError> {
+    sign_in_with(local_version, shorthand, Arc::new(TtyPrompt)).await
+}
+
+/// sign in to the account (shorthand) with the binary; the master password comes from the
+/// provider
+pub async fn sign_in_with(
+    local_version: &LocalVersion,
+    shorthand: &str,
+    password: Arc<dyn PasswordProvider>,
+) -> Result<Session, OpError> {
+    let sess_conf = session_config(local_version, shorthand, password);
+    match ReleaseNoteUrl::for_version(&local_version.version) {
+        ReleaseNoteUrl::V1 => sign_in_shorthand_v1(&sess_conf).await,
+        ReleaseNoteUrl::V2 => sign_in_shorthand_v2(&sess_conf).await,
+    }
+}
+
+/// the blocking api, for the tools that don't run a tokio runtime
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use std::sync::Arc;
+
+    use crate::session::blocking::*;
+    use crate::{
+        session_config, Account, LocalVersion, OpError, PasswordProvider, ReleaseNoteUrl, Session,
+        TtyPrompt,
+    };
+
+    pub use crate::session::blocking::{get_item, item_fields};
+
+    /// the accounts configured in the host system
+    pub fn local_accounts(local_version: &LocalVersion) -> Result<Vec<Account>, OpError> {
+        let sess_conf = session_config(local_version, "", Arc::new(TtyPrompt));
+        match ReleaseNoteUrl::for_version(&local_version.version) {
+            ReleaseNoteUrl::V1 => local_accounts_v1(&sess_conf),
+            ReleaseNoteUrl::V2 => local_accounts_v2(&sess_conf),
+        }
+    }
+
+    /// sign in to the account (shorthand) with the binary; prompt for the master password
+    pub fn sign_in(local_version: &LocalVersion, shorthand: &str) -> Result<Session, OpError> {
+        sign_in_with(local_version, shorthand, Arc::new(TtyPrompt))
+    }
+
+    /// sign in to the account (shorthand) with the binary; the master password comes from
+    /// the provider
+    pub fn sign_in_with(
+        local_version: &LocalVersion,
+        shorthand: &str,
+        password: Arc<dyn PasswordProvider>,
+    ) -> Result<Session, OpError> {
+        let sess_conf = session_config(local_version, shorthand, password);
+        match ReleaseNoteUrl::for_version(&local_version.version) {
+            ReleaseNoteUrl::V1 => sign_in_shorthand_v1(&sess_conf),
+            ReleaseNoteUrl::V2 => sign_in_shorthand_v2(&sess_conf),
+        }
+    }
+}
+
+/// the latest 2.x binary, installed or upgraded if needed
+async fn latest_binary(home_dir: &str) -> anyhow::Result<LocalVersion> {
+    let inst = get_or_install(
+        std::path::Path::new(home_dir),
+        ReleaseNoteUrl::V2,
+        &ReqwestTransport::default(),
+        &default_install_config(home_dir)?,
+    )
+    .await?;
+    Ok(inst.local_version)
+}
+
 pub async fn list_local_accounts() -> anyhow::Result<()> {
     let home_dir = get_or_create().await?;
-    let inst = get_or_install(std::path::Path::new(&home_dir), ReleaseNoteUrl::V2).await?;
-    let sess_conf = SessionConfig {
-        bin_filename: inst.local_version.path,
-        shorthand: String::new(),
-    };
-    let accounts = match inst.major_version {
-        ReleaseNoteUrl::V1 => local_accounts_v1(&sess_conf)?,
-        ReleaseNoteUrl::V2 => local_accounts_v2(&sess_conf)?,
-    };
-    for ref acc in accounts {
+    let lv = latest_binary(&home_dir).await?;
+    for ref acc in local_accounts(&lv).await? {
         println!("{:?}", acc);
     }
     Ok(())
@@ -25,13 +182,14 @@ pub async fn list_local_accounts() -> anyhow::Result<()> {
 
 pub async fn make_session(shorthand: &str) -> anyhow::Result<Session> {
     let home_dir = get_or_create().await?;
-    let inst = get_or_install(std::path::Path::new(&home_dir), ReleaseNoteUrl::V2).await?;
-    let sess_conf = SessionConfig {
-        bin_filename: inst.local_version.path,
-        shorthand: shorthand.to_string(),
-    };
-    match inst.major_version {
-        ReleaseNoteUrl::V1 => sign_in_shorthand_v1(&sess_conf),
-        ReleaseNoteUrl::V2 => sign_in_shorthand_v2(&sess_conf),
-    }
+    let lv = latest_binary(&home_dir).await?;
+    Ok(sign_in(&lv, shorthand).await?)
+}
+
+/// like make_session, but with the binary selected for the current directory (see
+/// selected_binary) instead of the latest 2.x release
+pub async fn make_selected_session(shorthand: &str) -> anyhow::Result<Session> {
+    let home_dir = get_or_create().await?;
+    let lv = selected_binary(&home_dir, &std::env::current_dir()?).await?;
+    Ok(sign_in(&lv, shorthand).await?)
 }
@@ -0,0 +1,264 @@
+// to read the payload of a macOS package, a cpio archive in either format:
+// - odc (070707): a 76-byte header of octal fields, the name, then the data;
+// - newc (070701, or 070702 with checksums): a 110-byte header of hex fields, the name
+//   and the data, each padded to a multiple of 4 bytes.
+// the entries are read in order until the TRAILER!!! entry.
+
+use std::io::{self, Read, Write};
+
+use thiserror::Error;
+
+const TRAILER: &str = "TRAILER!!!";
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+/// PATH_MAX; the header is not trusted beyond this
+const MAX_NAME_SIZE: u64 = 4096;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum CpioError {
+    #[error("not a cpio archive (bad magic): {0:?}")]
+    BadMagic(String),
+
+    #[error("malformed cpio header field: {0:?}")]
+    MalformedHeader(String),
+
+    #[error("can't find the file in the cpio archive: {0}")]
+    EntryNotFound(String),
+
+    #[error("cpio entry name too long: {0} bytes")]
+    NameTooLong(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Odc,
+    Newc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpioEntry {
+    pub name: String,
+    pub mode: u32,
+    pub size: u64,
+}
+
+impl CpioEntry {
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+/// reads the entries in order; the data of the current entry is read through the
+/// reader itself
+pub struct CpioReader<R: Read> {
+    inner: R,
+    /// the unread data of the current entry
+    remaining: u64,
+    /// the padding after the data of the current entry
+    padding: u64,
+}
+
+fn parse_field(bytes: &[u8], radix: u32) -> anyhow::Result<u64> {
+    let s = std::str::from_utf8(bytes)
+        .map_err(|_| CpioError::MalformedHeader(String::from_utf8_lossy(bytes).into_owned()))?;
+    Ok(u64::from_str_radix(s, radix).map_err(|_| CpioError::MalformedHeader(s.to_string()))?)
+}
+
+/// the bytes to pad `n` to a multiple of 4
+fn pad4(n: u64) -> u64 {
+    (4 - n % 4) % 4
+}
+
+impl<R: Read> CpioReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        io::copy(&mut (&mut self.inner).take(n), &mut io::sink())?;
+        Ok(())
+    }
+
+    /// move to the next entry, skipping the unread data of the current one;
+    /// None at the trailer
+    pub fn next_entry(&mut self) -> anyhow::Result<Option<CpioEntry>> {
+        self.skip(self.remaining + self.padding)?;
+        self.remaining = 0;
+        self.padding = 0;
+
+        let mut magic = [0u8; 6];
+        self.inner.read_exact(&mut magic)?;
+        let format = match &magic {
+            b"070707" => Format::Odc,
+            b"070701" | b"070702" => Format::Newc,
+            _ => {
+                return Err(
+                    CpioError::BadMagic(String::from_utf8_lossy(&magic).into_owned()).into(),
+                )
+            }
+        };
+        let (mode, namesize, filesize) = match format {
+            Format::Odc => {
+                // dev ino mode uid gid nlink rdev (6 each), mtime (11), namesize (6), filesize (11)
+                let mut header = [0u8; 70];
+                self.inner.read_exact(&mut header)?;
+                (
+                    parse_field(&header[12..18], 8)?,
+                    parse_field(&header[53..59], 8)?,
+                    parse_field(&header[59..70], 8)?,
+                )
+            }
+            Format::Newc => {
+                // ino mode uid gid nlink mtime filesize devmajor devminor rdevmajor rdevminor
+                // namesize check (8 each)
+                let mut header = [0u8; 104];
+                self.inner.read_exact(&mut header)?;
+                let field = |i: usize| parse_field(&header[i * 8..i * 8 + 8], 16);
+                (field(1)?, field(11)?, field(6)?)
+            }
+        };
+
+        if namesize > MAX_NAME_SIZE {
+            return Err(CpioError::NameTooLong(namesize).into());
+        }
+        let mut name = vec![0u8; namesize as usize];
+        self.inner.read_exact(&mut name)?;
+        if format == Format::Newc {
+            self.skip(pad4(110 + namesize))?;
+        }
+        let name = String::from_utf8_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string();
+        if name == TRAILER {
+            return Ok(None);
+        }
+        self.remaining = filesize;
+        if format == Format::Newc {
+            self.padding = pad4(filesize);
+        }
+        Ok(Some(CpioEntry {
+            name,
+            mode: mode as u32,
+            size: filesize,
+        }))
+    }
+}
+
+impl<R: Read> Read for CpioReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// copy the regular file named `name` (a leading ./ is ignored) to the writer;
+/// return the number of bytes copied
+pub fn extract_entry<R: Read, W: Write>(
+    reader: R,
+    name: &str,
+    writer: &mut W,
+) -> anyhow::Result<u64> {
+    let mut cpio = CpioReader::new(reader);
+    while let Some(entry) = cpio.next_entry()? {
+        if entry.is_file() && entry.name.trim_start_matches("./") == name {
+            return Ok(io::copy(&mut cpio, writer)?);
+        }
+    }
+    Err(CpioError::EntryNotFound(name.to_string()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("archives")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        libflate::gzip::Decoder::new(bytes)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_list_entries_odc_and_newc() {
+        for bytes in [
+            gunzip(&fixture("apple_pkg_expanded_gzip")),
+            fixture("payload_newc.cpio"),
+        ] {
+            let mut cpio = CpioReader::new(bytes.as_slice());
+            let mut names = Vec::new();
+            while let Some(entry) = cpio.next_entry().unwrap() {
+                names.push((entry.name.clone(), entry.is_file(), entry.size));
+            }
+            assert_eq!(
+                vec![
+                    (".".to_string(), false, 0),
+                    ("./README".to_string(), true, 15),
+                    ("./op".to_string(), true, 21),
+                ],
+                names
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_entry() {
+        for bytes in [
+            gunzip(&fixture("apple_pkg_expanded_gzip")),
+            fixture("payload_newc.cpio"),
+        ] {
+            let mut out = Vec::new();
+            let n = extract_entry(bytes.as_slice(), "op", &mut out).unwrap();
+            assert_eq!(21, n);
+            assert_eq!(b"#!/bin/sh\necho 2.1.0\n".to_vec(), out);
+
+            let err = extract_entry(bytes.as_slice(), "op.sig", &mut out).unwrap_err();
+            assert_eq!(
+                Some(&CpioError::EntryNotFound("op.sig".to_string())),
+                err.downcast_ref::<CpioError>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let err = extract_entry(&b"PK\x03\x04 not a cpio"[..], "op", &mut io::sink()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CpioError>(),
+            Some(CpioError::BadMagic(_))
+        ));
+    }
+
+    #[test]
+    fn test_name_too_long() {
+        // newc, all fields 0 but the namesize
+        let mut header = b"070701".to_vec();
+        for i in 0..13 {
+            header.extend_from_slice(if i == 11 { b"ffffffff" } else { b"00000000" });
+        }
+        let err = extract_entry(header.as_slice(), "op", &mut io::sink()).unwrap_err();
+        assert_eq!(
+            Some(&CpioError::NameTooLong(0xffff_ffff)),
+            err.downcast_ref::<CpioError>()
+        );
+    }
+}
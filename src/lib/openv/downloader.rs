@@ -1,41 +1,728 @@
+// to download a file with the multi-segment approach:
+// - probe the server with a one-byte range request to learn the total size;
+// - fetch the segments concurrently, each one into its own <basename>.<i>.part file;
+// - the validators (ETag/Last-Modified) and the total size of the content are saved in
+//   <basename>.part.json next to the parts;
+// - a segment resumes from the size of its .part file after an interruption, with If-Range,
+//   only if the probe still reports the saved validators and total size; the parts are
+//   thrown away otherwise, or if the server answers a resumed segment with the whole
+//   content (200), and the download starts over once;
+// - concatenate the segments and remove the .part files once all of them are complete;
+// - fall back to a single stream (<basename>.part) if the server doesn't support ranges;
+//   it is renamed only if it has as many bytes as the server announced.
+
+use crate::openv::progress::Progress;
+use crate::openv::transport::{Body, ContentKind, Transport, Validators};
 use anyhow::anyhow;
-use reqwest::StatusCode;
-use std::path::Path;
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 
+pub const DEFAULT_NUM_SEGMENTS: usize = 4;
+
+/// don't split the file into segments smaller than this
+const MIN_SEGMENT_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum DownloadError {
+    #[error("the content has changed during the download: {0}")]
+    ContentChanged(String),
+
+    #[error("the download is truncated: {received} of {total} bytes")]
+    Truncated { received: u64, total: u64 },
+}
+
+/// what the .part files are parts of
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PartsState {
+    validators: Validators,
+    total: u64,
+}
+
 #[allow(dead_code)]
-pub async fn download_url(o_dir: &Path, u: &str) -> anyhow::Result<String> {
-    let res = reqwest::get(u).await?;
-    if res.status() != StatusCode::OK {
-        return Err(anyhow!("request has been rejected: {}", u));
-    }
+pub async fn download_url(
+    transport: &dyn Transport,
+    o_dir: &Path,
+    u: &str,
+    progress: &Progress,
+) -> anyhow::Result<String> {
+    download_url_segmented(transport, o_dir, u, DEFAULT_NUM_SEGMENTS, progress).await
+}
+
+pub async fn download_url_segmented(
+    transport: &dyn Transport,
+    o_dir: &Path,
+    u: &str,
+    num_segments: usize,
+    progress: &Progress,
+) -> anyhow::Result<String> {
     let basename = match u.rsplit_once('/') {
         Some((_, x)) => x,
         None => "unnamed",
     };
     let o_filename = o_dir.join(basename);
-    let mut o_file = fs::File::create(&o_filename).await?;
-    let body = res.bytes().await?;
-    o_file.write_all(&body).await?;
-    Ok(o_filename.to_string_lossy().into_owned())
+    let mut restarted = false;
+    loop {
+        let probe = transport.get(u, Some((0, 0))).await?;
+        match probe.kind {
+            ContentKind::Partial { total } => {
+                let state = PartsState {
+                    validators: probe.validators.clone(),
+                    total,
+                };
+                drop(probe);
+                let segmented =
+                    download_segments(transport, u, &o_filename, &state, num_segments, progress);
+                match segmented.await {
+                    // the parts have been thrown away; start over once
+                    Err(e) if !restarted && e.downcast_ref::<DownloadError>().is_some() => {
+                        restarted = true;
+                        continue;
+                    }
+                    rs => rs?,
+                }
+            }
+            // the server ignores the range, therefore the download always starts over
+            ContentKind::Full { length } => {
+                download_single_stream(probe.body, length, &o_filename, progress).await?
+            }
+        }
+        return Ok(o_filename.to_string_lossy().into_owned());
+    }
+}
+
+fn state_filename(o_filename: &Path) -> PathBuf {
+    let mut s = o_filename.as_os_str().to_owned();
+    s.push(".part.json");
+    PathBuf::from(s)
+}
+
+/// keep the .part files only if they are parts of the same content; a content without
+/// validators can't be told apart, so its parts are never kept
+async fn reconcile_parts(
+    o_filename: &Path,
+    part_filenames: &[PathBuf],
+    state: &PartsState,
+) -> anyhow::Result<()> {
+    let saved = match fs::read_to_string(state_filename(o_filename)).await {
+        Ok(text) => serde_json::from_str::<PartsState>(&text).ok(),
+        Err(_) => None,
+    };
+    let resumable = state.validators.if_range().is_some() && saved.as_ref() == Some(state);
+    if !resumable {
+        remove_parts(o_filename, part_filenames).await?;
+    }
+    fs::write(state_filename(o_filename), serde_json::to_string(state)?).await?;
+    Ok(())
+}
+
+async fn remove_parts(o_filename: &Path, part_filenames: &[PathBuf]) -> anyhow::Result<()> {
+    for filename in part_filenames
+        .iter()
+        .chain(std::iter::once(&state_filename(o_filename)))
+    {
+        match fs::remove_file(filename).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+async fn download_segments(
+    transport: &dyn Transport,
+    u: &str,
+    o_filename: &Path,
+    state: &PartsState,
+    num_segments: usize,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    let total = state.total;
+    let segments = split_segments(total, num_segments);
+    let part_filenames: Vec<_> = (0..segments.len())
+        .map(|idx| segment_filename(o_filename, idx))
+        .collect();
+    reconcile_parts(o_filename, &part_filenames, state).await?;
+    let tracker = Tracker {
+        received: AtomicU64::new(0),
+        total,
+        progress,
+    };
+    let downloaded = try_join_all(segments.iter().zip(&part_filenames).map(
+        |((start, end), part_filename)| {
+            let segment = Segment {
+                part_filename,
+                start: *start,
+                end: *end,
+            };
+            download_segment(transport, u, segment, state, &tracker)
+        },
+    ))
+    .await;
+    if let Err(e) = downloaded {
+        if e.downcast_ref::<DownloadError>().is_some() {
+            remove_parts(o_filename, &part_filenames).await?;
+        }
+        return Err(e);
+    }
+    concat_segments(&part_filenames, o_filename).await?;
+    fs::remove_file(state_filename(o_filename)).await?;
+    let size = fs::metadata(o_filename).await?.len();
+    if size != total {
+        return Err(anyhow!(
+            "incomplete download: {}, expect {} bytes, got {}",
+            u,
+            total,
+            size
+        ));
+    }
+    Ok(())
+}
+
+/// split [0, total) into at most n inclusive (start, end) ranges
+fn split_segments(total: u64, n: usize) -> Vec<(u64, u64)> {
+    if total == 0 {
+        return Vec::new();
+    }
+    let n = (n.max(1) as u64).min((total / MIN_SEGMENT_SIZE).max(1));
+    let seg_size = total.div_ceil(n);
+    (0..n)
+        .map(|i| (i * seg_size, ((i + 1) * seg_size).min(total) - 1))
+        .filter(|(start, end)| start <= end)
+        .collect()
+}
+
+fn segment_filename(o_filename: &Path, idx: usize) -> PathBuf {
+    let mut s = o_filename.as_os_str().to_owned();
+    s.push(format!(".{}.part", idx));
+    PathBuf::from(s)
+}
+
+fn part_filename(o_filename: &Path) -> PathBuf {
+    let mut s = o_filename.as_os_str().to_owned();
+    s.push(".part");
+    PathBuf::from(s)
+}
+
+/// the bytes received by all the segments
+struct Tracker<'a> {
+    received: AtomicU64,
+    total: u64,
+    progress: &'a Progress,
+}
+
+impl Tracker<'_> {
+    fn add(&self, n: u64) {
+        let received = self.received.fetch_add(n, Ordering::SeqCst) + n;
+        self.progress.bytes(received, Some(self.total));
+    }
+}
+
+/// the inclusive range of the content, downloaded into the .part file
+struct Segment<'a> {
+    part_filename: &'a Path,
+    start: u64,
+    end: u64,
+}
+
+/// download the segment, resuming from the current size of its .part file
+async fn download_segment(
+    transport: &dyn Transport,
+    u: &str,
+    segment: Segment<'_>,
+    state: &PartsState,
+    tracker: &Tracker<'_>,
+) -> anyhow::Result<()> {
+    let Segment {
+        part_filename,
+        start,
+        end,
+    } = segment;
+    let expected = end - start + 1;
+    let received = match fs::metadata(part_filename).await {
+        Ok(metadata) if metadata.len() <= expected => metadata.len(),
+        _ => {
+            // no or corrupted .part file; start over
+            fs::File::create(part_filename).await?;
+            0
+        }
+    };
+    tracker.add(received);
+    if received == expected {
+        return Ok(());
+    }
+    let range = (start + received, end);
+    let mut res = match state.validators.if_range() {
+        Some(if_range) if received > 0 => transport.get_if_range(u, range, if_range).await?,
+        _ => transport.get(u, Some(range)).await?,
+    };
+    match res.kind {
+        ContentKind::Partial { total } if total == state.total => (),
+        // the whole (new) content or another size
+        ContentKind::Partial { .. } => {
+            return Err(DownloadError::ContentChanged(u.to_string()).into())
+        }
+        ContentKind::Full { .. } if received > 0 => {
+            return Err(DownloadError::ContentChanged(u.to_string()).into())
+        }
+        ContentKind::Full { .. } => return Err(anyhow!("range request has been rejected: {}", u)),
+    }
+    let mut o_file = fs::OpenOptions::new()
+        .append(true)
+        .open(part_filename)
+        .await?;
+    while let Some(chunk) = res.body.chunk().await? {
+        o_file.write_all(&chunk).await?;
+        tracker.add(chunk.len() as u64);
+    }
+    o_file.flush().await?;
+    let size = fs::metadata(part_filename).await?.len();
+    if size != expected {
+        return Err(anyhow!(
+            "incomplete segment: {:?}, expect {} bytes, got {}",
+            part_filename,
+            expected,
+            size
+        ));
+    }
+    Ok(())
+}
+
+async fn concat_segments(part_filenames: &[PathBuf], o_filename: &Path) -> anyhow::Result<()> {
+    let mut o_file = fs::File::create(o_filename).await?;
+    for part_filename in part_filenames {
+        let mut part = fs::File::open(part_filename).await?;
+        tokio::io::copy(&mut part, &mut o_file).await?;
+    }
+    o_file.flush().await?;
+    for part_filename in part_filenames {
+        fs::remove_file(part_filename).await?;
+    }
+    Ok(())
+}
+
+async fn download_single_stream(
+    mut body: Box<dyn Body>,
+    total: Option<u64>,
+    o_filename: &Path,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    let part_filename = part_filename(o_filename);
+    let mut o_file = fs::File::create(&part_filename).await?;
+    let mut received = 0;
+    let streamed = async {
+        while let Some(chunk) = body.chunk().await? {
+            o_file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            progress.bytes(received, total);
+        }
+        o_file.flush().await?;
+        match total {
+            Some(total) if total != received => {
+                Err(DownloadError::Truncated { received, total }.into())
+            }
+            _ => Ok(()),
+        }
+    };
+    // a single stream always starts over, so there is nothing to resume from
+    if let Err(e) = streamed.await {
+        let _dont_care = fs::remove_file(&part_filename).await;
+        return Err(e);
+    }
+    fs::rename(&part_filename, o_filename).await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::openv::progress::ProgressEvent;
+    use crate::openv::transport::{MemoryTransport, ReqwestTransport};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
     use tokio::runtime::Runtime;
 
+    const ETAG: &str = "\"v1\"";
+
+    /// a stand-in for the http server; it serves one body at any path and
+    /// records the range header of every request
+    struct FakeServer {
+        base_url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    async fn serve(body: Vec<u8>, support_ranges: bool) -> FakeServer {
+        serve_versions(vec![(body, ETAG)], support_ranges).await
+    }
+
+    /// the nth request gets the nth version of the body (and its etag), the last one
+    /// after; a range with a stale If-Range gets the whole body
+    async fn serve_versions(
+        versions: Vec<(Vec<u8>, &'static str)>,
+        support_ranges: bool,
+    ) -> FakeServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let recorded = ranges.clone();
+        let num_requests = Arc::new(AtomicU64::new(0));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let nth = num_requests.fetch_add(1, Ordering::SeqCst) as usize;
+                let (body, etag) = versions[nth.min(versions.len() - 1)].clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut req = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !req.ends_with(b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        req.extend_from_slice(&buf[..n]);
+                    }
+                    let req = String::from_utf8_lossy(&req).to_lowercase();
+                    let range = req
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .map(|x| x.trim().to_string());
+                    recorded.lock().unwrap().push(range.clone());
+                    let fresh = req
+                        .lines()
+                        .filter_map(|line| line.strip_prefix("if-range: "))
+                        .all(|x| x.trim() == etag);
+                    let total = body.len();
+                    let (status, content_range, slice) = match range {
+                        Some(r) if support_ranges && fresh => {
+                            let (start, end) = r.split_once('-').unwrap();
+                            let start = start.parse::<usize>().unwrap();
+                            let end = end.parse::<usize>().unwrap_or(total - 1).min(total - 1);
+                            (
+                                "206 Partial Content",
+                                format!("Content-Range: bytes {}-{}/{}\r\n", start, end, total),
+                                &body[start..=end],
+                            )
+                        }
+                        _ => ("200 OK", String::new(), &body[..]),
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nETag: {}\r\n{}Connection: close\r\n\r\n",
+                        status,
+                        slice.len(),
+                        etag,
+                        content_range
+                    );
+                    let _dont_care = stream.write_all(head.as_bytes()).await;
+                    let _dont_care = stream.write_all(slice).await;
+                });
+            }
+        });
+        FakeServer { base_url, ranges }
+    }
+
+    /// announces the whole body, but closes the connection halfway
+    async fn serve_truncated(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _dont_care = stream.read(&mut buf).await;
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _dont_care = stream.write_all(head.as_bytes()).await;
+                    let _dont_care = stream.write_all(&body[..body.len() / 2]).await;
+                });
+            }
+        });
+        base_url
+    }
+
+    fn fake_body() -> Vec<u8> {
+        (0..300 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join(name);
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        std::fs::create_dir_all(&dirname).unwrap();
+        dirname
+    }
+
     #[test]
     fn test_download_url() {
         let u = "https://cache.agilebits.com/dist/1P/op/pkg/v1.12.1/op_linux_amd64_v1.12.1.zip";
+        let archive = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("archives")
+                .join("op_linux_amd64_v1.11.2.zip"),
+        )
+        .unwrap();
+        let transport = MemoryTransport::new().with(u, archive.clone());
         let rt = Runtime::new().unwrap();
-        let o_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("testdata")
-            .join("tmp");
-        let fut = download_url(&o_dir, u);
+        let o_dir = tmp_dir("download_url");
+        let progress = Progress::default();
+        let fut = download_url(&transport, &o_dir, u, &progress);
         let result = rt.block_on(fut);
         assert!(result.is_ok());
-        assert!(result.unwrap().ends_with("op_linux_amd64_v1.12.1.zip"));
+        let o_filename = result.unwrap();
+        assert!(o_filename.ends_with("op_linux_amd64_v1.12.1.zip"));
+        assert_eq!(archive, std::fs::read(&o_filename).unwrap());
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_segments() {
+        assert!(split_segments(0, 4).is_empty());
+        assert_eq!(vec![(0, 99)], split_segments(100, 4));
+        let total = 4 * MIN_SEGMENT_SIZE + 1;
+        let segments = split_segments(total, 4);
+        assert_eq!(4, segments.len());
+        assert_eq!(0, segments[0].0);
+        assert_eq!(total - 1, segments[3].1);
+        for pair in segments.windows(2) {
+            assert_eq!(pair[0].1 + 1, pair[1].0);
+        }
+    }
+
+    #[test]
+    fn test_download_multi_segments() {
+        let rt = Runtime::new().unwrap();
+        let body = fake_body();
+        let o_dir = tmp_dir("download_multi_segments");
+        let server = rt.block_on(serve(body.clone(), true));
+        let u = format!("{}/dist/op_linux_amd64_v1.12.1.zip", server.base_url);
+        let last = Arc::new(Mutex::new(None));
+        let recorded = last.clone();
+        let progress = Progress::new(move |ev: &ProgressEvent| {
+            *recorded.lock().unwrap() = Some(ev.clone());
+        });
+        let o_filename = rt
+            .block_on(download_url(
+                &ReqwestTransport::default(),
+                &o_dir,
+                &u,
+                &progress,
+            ))
+            .unwrap();
+        assert_eq!(body, std::fs::read(&o_filename).unwrap());
+        assert_eq!(
+            Some(ProgressEvent::Bytes {
+                received: body.len() as u64,
+                total: Some(body.len() as u64)
+            }),
+            *last.lock().unwrap()
+        );
+        // the probe and one request per segment
+        assert_eq!(5, server.ranges.lock().unwrap().len());
+        // no .part file is left behind
+        assert_eq!(1, std::fs::read_dir(&o_dir).unwrap().count());
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_download_resume_partial_segment() {
+        let rt = Runtime::new().unwrap();
+        let body = fake_body();
+        let o_dir = tmp_dir("download_resume_partial_segment");
+        let server = rt.block_on(serve(body.clone(), true));
+        let u = format!("{}/op_linux_amd64_v1.12.1.zip", server.base_url);
+        let segments = split_segments(body.len() as u64, DEFAULT_NUM_SEGMENTS);
+
+        // the first segment is complete, the second one is interrupted halfway
+        let o_filename = o_dir.join("op_linux_amd64_v1.12.1.zip");
+        let (s0, e0) = segments[0];
+        let (s1, _) = segments[1];
+        std::fs::write(
+            segment_filename(&o_filename, 0),
+            &body[s0 as usize..=e0 as usize],
+        )
+        .unwrap();
+        std::fs::write(
+            segment_filename(&o_filename, 1),
+            &body[s1 as usize..s1 as usize + 1000],
+        )
+        .unwrap();
+        write_state(&o_filename, ETAG, body.len());
+
+        let o = rt
+            .block_on(download_url(
+                &ReqwestTransport::default(),
+                &o_dir,
+                &u,
+                &Progress::default(),
+            ))
+            .unwrap();
+        assert_eq!(body, std::fs::read(&o).unwrap());
+        let ranges = server.ranges.lock().unwrap().clone();
+        assert!(!ranges.contains(&Some(format!("{}-{}", s0, e0))));
+        assert!(ranges
+            .iter()
+            .flatten()
+            .any(|r| r.starts_with(&format!("{}-", s1 + 1000))));
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    fn write_state(o_filename: &Path, etag: &str, total: usize) {
+        let state = PartsState {
+            validators: Validators {
+                etag: Some(etag.to_string()),
+                last_modified: None,
+            },
+            total: total as u64,
+        };
+        std::fs::write(
+            state_filename(o_filename),
+            serde_json::to_string(&state).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_download_discards_parts_of_other_content() {
+        let rt = Runtime::new().unwrap();
+        let body = fake_body();
+        let o_dir = tmp_dir("download_discards_parts");
+        let server = rt.block_on(serve(body.clone(), true));
+        let u = format!("{}/op_linux_amd64_v1.12.1.zip", server.base_url);
+        let segments = split_segments(body.len() as u64, DEFAULT_NUM_SEGMENTS);
+        let o_filename = o_dir.join("op_linux_amd64_v1.12.1.zip");
+        let (s1, _) = segments[1];
+        let stale = vec![0xffu8; 1000];
+
+        for state in [Some("\"v0\""), None] {
+            std::fs::write(segment_filename(&o_filename, 1), &stale).unwrap();
+            if let Some(etag) = state {
+                write_state(&o_filename, etag, body.len());
+            }
+            server.ranges.lock().unwrap().clear();
+            let o = rt
+                .block_on(download_url(
+                    &ReqwestTransport::default(),
+                    &o_dir,
+                    &u,
+                    &Progress::default(),
+                ))
+                .unwrap();
+            assert_eq!(body, std::fs::read(&o).unwrap());
+            let ranges = server.ranges.lock().unwrap().clone();
+            assert!(!ranges
+                .iter()
+                .flatten()
+                .any(|r| r.starts_with(&format!("{}-", s1 + 1000))));
+            assert_eq!(1, std::fs::read_dir(&o_dir).unwrap().count());
+        }
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_download_restarts_if_content_changes() {
+        let rt = Runtime::new().unwrap();
+        let old = fake_body();
+        let new: Vec<u8> = old.iter().map(|b| b.wrapping_add(1)).collect();
+        let o_dir = tmp_dir("download_restarts_if_content_changes");
+        // the probe still sees the old content, the segments the new one
+        let server = rt.block_on(serve_versions(
+            vec![(old.clone(), ETAG), (new.clone(), "\"v2\"")],
+            true,
+        ));
+        let u = format!("{}/op_linux_amd64_v1.12.1.zip", server.base_url);
+        let segments = split_segments(old.len() as u64, DEFAULT_NUM_SEGMENTS);
+        let o_filename = o_dir.join("op_linux_amd64_v1.12.1.zip");
+        let (s1, _) = segments[1];
+        std::fs::write(
+            segment_filename(&o_filename, 1),
+            &old[s1 as usize..s1 as usize + 1000],
+        )
+        .unwrap();
+        write_state(&o_filename, ETAG, old.len());
+
+        let o = rt
+            .block_on(download_url(
+                &ReqwestTransport::default(),
+                &o_dir,
+                &u,
+                &Progress::default(),
+            ))
+            .unwrap();
+        assert_eq!(new, std::fs::read(&o).unwrap());
+        assert_eq!(1, std::fs::read_dir(&o_dir).unwrap().count());
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_download_fall_back_to_single_stream() {
+        let rt = Runtime::new().unwrap();
+        let body = fake_body();
+        let o_dir = tmp_dir("download_single_stream");
+        let server = rt.block_on(serve(body.clone(), false));
+        let u = format!("{}/op_linux_amd64_v1.12.1.zip", server.base_url);
+        let o_filename = rt
+            .block_on(download_url(
+                &ReqwestTransport::default(),
+                &o_dir,
+                &u,
+                &Progress::default(),
+            ))
+            .unwrap();
+        assert_eq!(body, std::fs::read(&o_filename).unwrap());
+        assert_eq!(1, std::fs::read_dir(&o_dir).unwrap().count());
+        std::fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_download_single_stream_rejects_truncated_body() {
+        let rt = Runtime::new().unwrap();
+        let o_dir = tmp_dir("download_single_stream_truncated");
+        let base_url = rt.block_on(serve_truncated(fake_body()));
+        let u = format!("{}/op_linux_amd64_v1.12.1.zip", base_url);
+        let rs = rt.block_on(download_url(
+            &ReqwestTransport::default(),
+            &o_dir,
+            &u,
+            &Progress::default(),
+        ));
+        assert!(rs.is_err());
+        assert_eq!(0, std::fs::read_dir(&o_dir).unwrap().count());
+
+        // a body shorter than announced, even if the transport doesn't notice
+        let o_filename = o_dir.join("op.zip");
+        let transport = MemoryTransport::new().with("https://x/op.zip", vec![0u8; 10]);
+        let body = rt
+            .block_on(transport.get("https://x/op.zip", None))
+            .unwrap()
+            .body;
+        let err = rt
+            .block_on(download_single_stream(
+                body,
+                Some(20),
+                &o_filename,
+                &Progress::default(),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            Some(&DownloadError::Truncated {
+                received: 10,
+                total: 20
+            }),
+            err.downcast_ref::<DownloadError>()
+        );
+        assert_eq!(0, std::fs::read_dir(&o_dir).unwrap().count());
+        std::fs::remove_dir_all(&o_dir).unwrap();
     }
 }
@@ -1,9 +1,18 @@
+// to find the openv root, in order:
+// - the directory given explicitly (e.g. openv --root);
+// - $OPENV_ROOT;
+// - ~/.op_cli, if it exists (the root of the earlier releases);
+// - openv under the data directory: $XDG_DATA_HOME, or ~/.local/share on linux.
+
 use std::fs::Permissions;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use dirs::home_dir;
 use tokio::fs;
 
+pub const ROOT_ENV: &str = "OPENV_ROOT";
+const LEGACY_DIRNAME: &str = ".op_cli";
+const DATA_DIRNAME: &str = "openv";
+
 #[cfg(target_family = "unix")]
 async fn handle_permission(p: &PathBuf, mut perms: Permissions) -> std::io::Result<()> {
     use std::os::unix::fs::PermissionsExt;
@@ -19,14 +28,51 @@ async fn handle_permission(_p: &PathBuf, mut _perms: Permissions) -> std::io::Re
     Ok(())
 }
 
+/// the openv root (see above); it may not exist yet
+pub fn root_dir(explicit: Option<&Path>) -> anyhow::Result<PathBuf> {
+    root_dir_with(
+        explicit,
+        |k| std::env::var(k).ok(),
+        dirs::home_dir(),
+        dirs::data_dir(),
+    )
+}
+
+fn root_dir_with<F: Fn(&str) -> Option<String>>(
+    explicit: Option<&Path>,
+    lookup: F,
+    home_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    if let Some(p) = explicit {
+        return Ok(p.to_path_buf());
+    }
+    if let Some(p) = lookup(ROOT_ENV).filter(|v| !v.trim().is_empty()) {
+        return Ok(PathBuf::from(p));
+    }
+    if let Some(legacy) = home_dir.map(|hd| hd.join(LEGACY_DIRNAME)) {
+        if legacy.is_dir() {
+            return Ok(legacy);
+        }
+    }
+    data_dir.map(|dd| dd.join(DATA_DIRNAME)).ok_or_else(|| {
+        anyhow::anyhow!(
+            "failed to find a directory for openv; set {} to choose one.",
+            ROOT_ENV
+        )
+    })
+}
+
 pub async fn get_or_create() -> anyhow::Result<String> {
-    const ONE_PASSWORD_HOME_DIR: &str = ".op_cli";
-    let hd = home_dir()
-        .ok_or_else(|| anyhow::anyhow!("failed to retrieve the home directory."))?
-        .join(ONE_PASSWORD_HOME_DIR);
+    get_or_create_in(None).await
+}
+
+/// the openv root, created if needed; `root` overrides the default (see above)
+pub async fn get_or_create_in(root: Option<&Path>) -> anyhow::Result<String> {
+    let hd = root_dir(root)?;
     let opt_metadata = fs::metadata(&hd).await;
     if opt_metadata.is_err() || !opt_metadata.unwrap().file_type().is_dir() {
-        fs::create_dir(&hd).await?;
+        fs::create_dir_all(&hd).await?;
     }
     let metadata = fs::metadata(&hd).await?;
     #[allow(unused_mut)]
@@ -47,4 +93,38 @@ mod test {
         let o = rt.block_on(get_or_create());
         assert!(o.is_ok());
     }
+
+    #[test]
+    fn test_root_dir_precedence() {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let no_env = |_: &str| None;
+        let env = |_: &str| Some("/srv/openv".to_string());
+        let data_dir = Some(PathBuf::from("/home/u/.local/share"));
+
+        let explicit = Path::new("/opt/openv");
+        assert_eq!(
+            explicit,
+            root_dir_with(Some(explicit), env, None, None).unwrap()
+        );
+        assert_eq!(
+            PathBuf::from("/srv/openv"),
+            root_dir_with(None, env, None, data_dir.clone()).unwrap()
+        );
+        // no ~/.op_cli there
+        assert_eq!(
+            PathBuf::from("/home/u/.local/share/openv"),
+            root_dir_with(None, no_env, Some(testdata.clone()), data_dir.clone()).unwrap()
+        );
+
+        let home = testdata.join("tmp").join("root_dir_precedence");
+        std::fs::create_dir_all(home.join(LEGACY_DIRNAME)).unwrap();
+        assert_eq!(
+            home.join(LEGACY_DIRNAME),
+            root_dir_with(None, no_env, Some(home.clone()), data_dir).unwrap()
+        );
+        std::fs::remove_dir_all(&home).unwrap();
+
+        // e.g. a container without $HOME
+        assert!(root_dir_with(None, no_env, None, None).is_err());
+    }
 }
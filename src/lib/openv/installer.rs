@@ -1,24 +1,121 @@
 use std::path::Path;
 
 use crate::openv::downloader::download_url;
-use crate::openv::local_versions::find_local_version;
-use crate::openv::op_release::{download_release_notes, parse_release_notes};
-use crate::openv::settings::ReleaseNoteUrl;
+use crate::openv::layout::{
+    read_manifest, version_dir, version_dirname, versions_dir, write_manifest, Channel,
+    InstallManifest,
+};
+use crate::openv::local_versions::{
+    find_local_version_for, find_local_versions_for, find_matching_local_version_for,
+};
+use crate::openv::op_release::{download_release_notes, parse_release_history};
+use crate::openv::progress::{Phase, Progress};
+use crate::openv::release_cache::now_secs;
+use crate::openv::settings::{InstallConfig, ReleaseNoteUrl};
+use crate::openv::transport::Transport;
 use crate::openv::types::*;
-use crate::openv::unpacker::{unpack_apple_gzip, unpack_apple_pkg, unpack_one_to, UnpackOption};
+use crate::openv::unpacker::{unpack_apple_pkg, unpack_one_to, UnpackOption};
+use crate::openv::verifier::{
+    sha256_file, verify_sha256, verify_signature, SignatureCheck, VerificationError,
+};
+use fs2::FileExt;
+use semver::VersionReq;
+use thiserror::Error;
 use tokio::fs;
 
+#[derive(Debug, PartialEq, Error)]
+pub enum InstallError {
+    #[error("can't find any release matching: {0}")]
+    NoMatchingRelease(String),
+
+    #[error("offline, and no local version matching {0} is installed")]
+    NotInstalledOffline(String),
+
+    #[error("offline, and the release notes are not cached: {0}")]
+    NotCachedOffline(String),
+
+    #[error("{0} holds the installation of another version or platform")]
+    VersionDirTaken(String),
+}
+
+/// the lock file in the home directory, held while installing
+pub const LOCK_FILENAME: &str = ".openv.lock";
+/// where the archives are downloaded and unpacked before the binary is moved into place;
+/// an interrupted download leaves its parts here to be resumed, but the directory is removed
+/// once the archive is complete, whether it unpacks and verifies or not
+pub const STAGING_DIRNAME: &str = ".openv-staging";
+
+/// an exclusive lock on the home directory; released when dropped
+struct InstallLock(std::fs::File);
+
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        // closing the file releases the lock regardless
+        let _dont_care = FileExt::unlock(&self.0);
+    }
+}
+
+impl InstallLock {
+    async fn acquire(dirname: &Path) -> anyhow::Result<Self> {
+        let filename = dirname.join(LOCK_FILENAME);
+        let file = tokio::task::spawn_blocking(move || -> std::io::Result<std::fs::File> {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&filename)?;
+            file.lock_exclusive()?;
+            Ok(file)
+        })
+        .await??;
+        Ok(Self(file))
+    }
+}
+
 #[allow(dead_code)]
 pub async fn get_or_install(
     dirname: &Path,
     release_note_url: ReleaseNoteUrl,
+    transport: &dyn Transport,
+    config: &InstallConfig,
 ) -> anyhow::Result<Installation> {
-    let rl_notes = download_release_notes(&release_note_url).await?;
-    let release = parse_release_notes(&rl_notes)?;
+    let target = config.target_platform();
+    if config.offline {
+        let lv = find_local_version_for(dirname, &target)
+            .await
+            .map_err(|_| InstallError::NotInstalledOffline("*".to_string()))?;
+        config.progress.completed(&lv.path);
+        return Ok(Installation {
+            major_version: release_note_url,
+            local_version: lv,
+            release: None,
+        });
+    }
+    let history = match fetch_release_history(&release_note_url, transport, config).await {
+        Ok(history) => history,
+        // e.g. the network is unreachable; carry on with the installed binary
+        Err(e) => {
+            let lv = find_local_version_for(dirname, &target)
+                .await
+                .map_err(|_| e)?;
+            config.progress.completed(&lv.path);
+            return Ok(Installation {
+                major_version: release_note_url,
+                local_version: lv,
+                release: None,
+            });
+        }
+    };
+    let release = history
+        .iter()
+        .find_map(|entry| entry.download_for(&target).cloned())
+        .map(|rl| config.mirror.rewrite(rl))
+        .ok_or(HtmlParsingError::MissingPlatform(target))?;
 
     // compare the local version to the release version
-    if let Ok(lv) = find_local_version(dirname).await {
+    if let Ok(lv) = find_local_version_for(dirname, &target).await {
         if lv.version >= release.version {
+            config.progress.completed(&lv.path);
             return Ok(Installation {
                 major_version: release_note_url,
                 local_version: lv,
@@ -27,54 +124,276 @@ pub async fn get_or_install(
         }
     }
 
-    let o_filename = download_url(dirname, &release.url).await?;
-    let archive_filename = Path::new(&o_filename);
+    let lv = install_release(dirname, &release, transport, config).await?;
+    config.progress.completed(&lv.path);
+    Ok(Installation {
+        major_version: release_note_url,
+        local_version: lv,
+        release: Some(release),
+    })
+}
 
-    let (_, binary_filename) = if cfg!(target_os = "macos") {
-        let p = "/tmp/pkgutil_workdir";
-        let _dont_care = fs::remove_dir_all(p).await;
-        let gzip_filename = unpack_apple_pkg(archive_filename, p.as_ref())?;
-        let basename = archive_filename
-            .iter()
-            .last()
-            .ok_or_else(|| anyhow::anyhow!("irregular path (no basename): {:?}", archive_filename))?
-            .to_str()
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "irregular path (cannot convert to str): {:?}",
-                    archive_filename
-                )
-            })?;
-        let (basename_clean, _) = basename
-            .rsplit_once('.')
-            .ok_or_else(|| anyhow::anyhow!("no file extension: {:?}", archive_filename))?;
-        let o = unpack_apple_gzip(gzip_filename.as_ref(), dirname, "op", Some(basename_clean))?;
-        fs::remove_file(gzip_filename).await?;
-        o
-    } else {
-        let unpack_opt = UnpackOption::UseArchiveName("op".to_string());
-        unpack_one_to(archive_filename, unpack_opt, dirname)?
-    };
-    fs::remove_file(&archive_filename).await?;
+/// install the newest release that satisfies the version requirement;
+/// the release notes are not downloaded if a matching local version exists
+pub async fn install_version(
+    dirname: &Path,
+    release_note_url: ReleaseNoteUrl,
+    req: &VersionReq,
+    transport: &dyn Transport,
+    config: &InstallConfig,
+) -> anyhow::Result<Installation> {
+    let target = config.target_platform();
+    if let Ok(lv) = find_matching_local_version_for(dirname, req, &target).await {
+        config.progress.completed(&lv.path);
+        return Ok(Installation {
+            major_version: release_note_url,
+            local_version: lv,
+            release: None,
+        });
+    }
+    if config.offline {
+        return Err(InstallError::NotInstalledOffline(req.to_string()).into());
+    }
+
+    let history = fetch_release_history(&release_note_url, transport, config).await?;
+    let release = history
+        .iter()
+        .filter(|entry| req.matches(&entry.version))
+        .filter_map(|entry| entry.download_for(&target).cloned())
+        .max_by(|l, r| l.version.cmp(&r.version))
+        .map(|rl| config.mirror.rewrite(rl))
+        .ok_or_else(|| InstallError::NoMatchingRelease(req.to_string()))?;
+
+    let lv = install_release(dirname, &release, transport, config).await?;
+    config.progress.completed(&lv.path);
     Ok(Installation {
         major_version: release_note_url,
-        local_version: LocalVersion {
-            version: release.version.clone(),
-            platform: release.platform,
-            path: binary_filename,
-        },
+        local_version: lv,
         release: Some(release),
     })
 }
 
+/// the release history (newest first), through the cache if there's one; only from the
+/// cache when offline
+pub async fn fetch_release_history(
+    release_note_url: &ReleaseNoteUrl,
+    transport: &dyn Transport,
+    config: &InstallConfig,
+) -> anyhow::Result<Vec<ReleaseEntry>> {
+    config.progress.phase(Phase::FetchingReleaseNotes);
+    let url = config.mirror.release_notes_url(release_note_url);
+    if config.offline {
+        let cached = match &config.release_notes_cache {
+            Some(cache) => cache.cached_release_history(release_note_url, &url).await,
+            None => None,
+        };
+        return cached.ok_or_else(|| InstallError::NotCachedOffline(url).into());
+    }
+    match &config.release_notes_cache {
+        Some(cache) => {
+            cache
+                .release_history(transport, release_note_url, &url)
+                .await
+        }
+        None => parse_release_history(&download_release_notes(transport, &url).await?),
+    }
+}
+
+/// download and unpack the release binary to versions/<version>-<platform>/ in the directory,
+/// along with the install manifest; the archive is always removed, so is the binary if it
+/// fails the verification
+async fn install_release(
+    dirname: &Path,
+    release: &Release,
+    transport: &dyn Transport,
+    config: &InstallConfig,
+) -> anyhow::Result<LocalVersion> {
+    let _lock = InstallLock::acquire(dirname).await?;
+    // another process may have installed it while this one waited for the lock
+    if let Some(lv) = find_local_versions_for(dirname, &release.platform)
+        .await?
+        .into_iter()
+        .find(|lv| lv.version == release.version && lv.platform == release.platform)
+    {
+        return Ok(lv);
+    }
+
+    let staging = dirname.join(STAGING_DIRNAME);
+    fs::create_dir_all(&staging).await?;
+    config.progress.phase(Phase::Downloading);
+    let o_filename = download_url(transport, &staging, &release.url, &config.progress).await?;
+    let archive_filename = Path::new(&o_filename);
+    let result = match unpack_and_verify(archive_filename, &staging, &release.platform, config)
+        .await
+    {
+        Ok(staged) => move_into_place(Path::new(&staged), archive_filename, dirname, release).await,
+        Err(e) => Err(e),
+    };
+    // the outcome stands even if the cleanup fails; the next installation starts over
+    let _dont_care = fs::remove_dir_all(&staging).await;
+    result
+}
+
+/// assemble the version directory (the binary and the manifest) in the staging directory,
+/// then rename it into place, so that the binary is never seen half-written or without
+/// the manifest; a version directory without a readable manifest (corrupt, or written by an
+/// earlier openv) is not an installation and is replaced; the caller holds the install lock
+async fn move_into_place(
+    staged: &Path,
+    archive_filename: &Path,
+    dirname: &Path,
+    release: &Release,
+) -> anyhow::Result<LocalVersion> {
+    let target = version_dir(dirname, &release.version, &release.platform);
+    if target.exists() {
+        if read_manifest(&target).await.is_ok() {
+            return Err(
+                InstallError::VersionDirTaken(target.to_string_lossy().into_owned()).into(),
+            );
+        }
+        fs::remove_dir_all(&target).await?;
+    }
+    let binary_name = release.platform.binary_name();
+    let manifest = InstallManifest {
+        version: release.version.clone(),
+        platform: release.platform,
+        url: release.url.clone(),
+        sha256: sha256_file(staged)?,
+        archive_sha256: sha256_file(archive_filename)?,
+        installed_at: now_secs(),
+        channel: Channel::of(&release.version),
+    };
+    let staged_dir = staged.with_file_name(version_dirname(&release.version, &release.platform));
+    fs::create_dir_all(&staged_dir).await?;
+    fs::rename(staged, staged_dir.join(binary_name)).await?;
+    write_manifest(&staged_dir, &manifest).await?;
+    fs::create_dir_all(versions_dir(dirname)).await?;
+    fs::rename(&staged_dir, &target).await?;
+    Ok(LocalVersion {
+        version: release.version.clone(),
+        platform: release.platform,
+        path: target.join(binary_name).to_string_lossy().into_owned(),
+        manifest: Some(manifest),
+    })
+}
+
+/// unpack the binary of the platform, e.g. op.exe from a windows archive
+async fn unpack_and_verify(
+    archive_filename: &Path,
+    dirname: &Path,
+    platform: &Platform,
+    config: &InstallConfig,
+) -> anyhow::Result<String> {
+    if let Some(expected) = &config.sha256 {
+        config.progress.phase(Phase::Verifying);
+        verify_sha256(archive_filename, expected)?;
+    }
+
+    config.progress.phase(Phase::Unpacking);
+    let basename = archive_filename
+        .file_stem()
+        .ok_or_else(|| anyhow::anyhow!("irregular path (no basename): {:?}", archive_filename))?
+        .to_string_lossy()
+        .into_owned();
+    let is_pkg = archive_filename.extension() == Some("pkg".as_ref());
+    let (_, binary_filename) = if is_pkg {
+        unpack_apple_pkg(archive_filename, dirname, &basename, &config.progress)?
+    } else {
+        let unpack_opt = UnpackOption::UseArchiveName(platform.binary_name().to_string());
+        unpack_one_to(archive_filename, unpack_opt, dirname, &config.progress)?
+    };
+
+    // a macOS package ships no op.sig; it is signed by the Apple installer certificate
+    // instead, which the gpg check can't verify, so only the digest (if pinned) covers it
+    if let Some(check) = config.signature.as_ref().filter(|_| !is_pkg) {
+        config.progress.phase(Phase::Verifying);
+        let verified = verify_archive_signature(
+            archive_filename,
+            dirname,
+            platform,
+            Path::new(&binary_filename),
+            check,
+        );
+        if verified.is_err() {
+            fs::remove_file(&binary_filename).await?;
+            return verified.map(|_| binary_filename);
+        }
+    }
+    Ok(binary_filename)
+}
+
+/// the signature (op.sig, or op.exe.sig) is unpacked next to the binary and removed after
+/// the verification
+fn verify_archive_signature(
+    archive_filename: &Path,
+    dirname: &Path,
+    platform: &Platform,
+    binary_filename: &Path,
+    check: &SignatureCheck,
+) -> anyhow::Result<()> {
+    let unpack_opt = UnpackOption::UseEntryName(format!("{}.sig", platform.binary_name()));
+    let (_, sig_filename) =
+        unpack_one_to(archive_filename, unpack_opt, dirname, &Progress::default()).map_err(
+            |_| {
+                VerificationError::MissingSignature(archive_filename.to_string_lossy().into_owned())
+            },
+        )?;
+    let verified = verify_signature(binary_filename, sig_filename.as_ref(), check);
+    std::fs::remove_file(&sig_filename)?;
+    verified
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::openv::layout::VERSIONS_DIRNAME;
+    use crate::openv::progress::ProgressEvent;
+    use crate::openv::settings::Mirror;
+    use crate::openv::transport::MemoryTransport;
+    use crate::openv::verifier::verify_installation;
     use std::fs;
     use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
     use tokio::runtime::Runtime;
 
+    /// serves the release notes fixture, and the archive fixture at every download url
+    /// of every platform (the windows one has op.exe), both at the (mirrored) urls
+    fn fixture_transport(release_note_url: &ReleaseNoteUrl, mirror: &Mirror) -> MemoryTransport {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let release_notes = fs::read_to_string(
+            testdata
+                .join("release_notes")
+                .join("2021_11_14_release_notes.html"),
+        )
+        .unwrap();
+        let archive =
+            fs::read(testdata.join("archives").join("op_linux_amd64_v1.11.2.zip")).unwrap();
+        let windows_archive = fs::read(
+            testdata
+                .join("archives")
+                .join("op_windows_amd64_v2.1.0.zip"),
+        )
+        .unwrap();
+        let mut transport = MemoryTransport::new();
+        for entry in parse_release_history(&release_notes).unwrap() {
+            for rl in entry.downloads {
+                let archive = match rl.platform.os {
+                    OperatingSystem::Windows => windows_archive.clone(),
+                    _ => archive.clone(),
+                };
+                transport.insert(&mirror.download_url(&rl.url), archive);
+            }
+        }
+        transport.insert(
+            &mirror.release_notes_url(release_note_url),
+            release_notes.into_bytes(),
+        );
+        transport
+    }
+
     #[test]
+    #[cfg(not(target_os = "macos"))]
     fn test_ensure_installed_for_the_first_time() {
         // empty the directory
         let dirname: PathBuf = [
@@ -88,13 +407,41 @@ mod test {
         let _dont_care = fs::remove_dir_all(&dirname);
         assert!(fs::create_dir_all(&dirname).is_ok());
 
-        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1);
+        let config = InstallConfig::default();
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
         let rt = Runtime::new().unwrap();
         let rs = rt.block_on(fut);
         assert!(rs.is_ok());
         let inst = rs.unwrap();
         // has a release value
         assert!(inst.release.is_some());
+        assert_eq!(semver::Version::new(1, 12, 3), inst.local_version.version);
+        assert!(fs::metadata(&inst.local_version.path).unwrap().is_file());
+        // the manifest is next to the binary
+        let vdir = version_dir(
+            &dirname,
+            &inst.local_version.version,
+            &inst.local_version.platform,
+        );
+        let manifest = rt.block_on(read_manifest(&vdir)).unwrap();
+        assert_eq!(inst.local_version.manifest.as_ref(), Some(&manifest));
+        assert_eq!(inst.release.unwrap().url, manifest.url);
+        assert_eq!(Channel::Stable, manifest.channel);
+        assert_eq!(
+            "7a2bc2f3b81897f7ee9592186e89b1694ef81bd604f8b6fcdd49fc88a737e177",
+            manifest.archive_sha256
+        );
+        assert!(verify_installation(&inst.local_version).is_ok());
+
+        // tampered with
+        fs::write(&inst.local_version.path, b"#!/bin/sh\n").unwrap();
+        assert!(matches!(
+            verify_installation(&inst.local_version)
+                .unwrap_err()
+                .downcast_ref::<VerificationError>(),
+            Some(VerificationError::ChecksumMismatch { .. })
+        ));
         assert!(fs::remove_dir_all(&dirname).is_ok());
     }
 
@@ -115,7 +462,9 @@ mod test {
         let filename = dirname.join("op_linux_amd64_v1.13.15");
         assert!(fs::File::create(&filename).is_ok());
 
-        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1);
+        let config = InstallConfig::default();
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
         let rt = Runtime::new().unwrap();
         let rs = rt.block_on(fut);
         assert!(rs.is_ok());
@@ -123,8 +472,566 @@ mod test {
         // doesn't have the release value (local installation)
         assert!(inst.release.is_none());
 
-        // assert_eq!(Version::new(1, 13, 15), inst.local_version.version);
+        assert_eq!(semver::Version::new(1, 13, 15), inst.local_version.version);
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_install_version_get_preinstalled_binary() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_version_preinstalled_binary",
+        ]
+        .iter()
+        .collect();
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        assert!(fs::File::create(dirname.join("op_linux_amd64_v1.10.3")).is_ok());
+        assert!(fs::File::create(dirname.join("op_linux_amd64_v1.11.2")).is_ok());
+
+        let req = VersionReq::parse("~1.10").unwrap();
+        let config = InstallConfig::default();
+        let transport = MemoryTransport::new();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        // the pinned version is installed locally; nothing is downloaded
+        assert!(inst.release.is_none());
+        assert_eq!(semver::Version::new(1, 10, 3), inst.local_version.version);
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_unpack_and_verify_checksum_mismatch_leaves_nothing() {
+        let archive_filename: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "archives",
+            "op_linux_amd64_v1.11.2.zip",
+        ]
+        .iter()
+        .collect();
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "unpack_and_verify_checksum",
+        ]
+        .iter()
+        .collect();
+        let linux_amd64 = Platform::from_str("linux_amd64").unwrap();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        let rt = Runtime::new().unwrap();
+
+        let config = InstallConfig {
+            sha256: Some("deadbeef".to_string()),
+            ..InstallConfig::default()
+        };
+        let rs = rt.block_on(unpack_and_verify(
+            &archive_filename,
+            &dirname,
+            &linux_amd64,
+            &config,
+        ));
+        assert!(matches!(
+            rs.unwrap_err().downcast_ref::<VerificationError>(),
+            Some(VerificationError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(0, fs::read_dir(&dirname).unwrap().count());
+
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let recorded = phases.clone();
+        let config = InstallConfig {
+            sha256: Some(
+                "7a2bc2f3b81897f7ee9592186e89b1694ef81bd604f8b6fcdd49fc88a737e177".to_string(),
+            ),
+            progress: Progress::new(move |ev: &ProgressEvent| {
+                if let ProgressEvent::Phase(phase) = ev {
+                    recorded.lock().unwrap().push(*phase);
+                }
+            }),
+            ..InstallConfig::default()
+        };
+        let rs = rt.block_on(unpack_and_verify(
+            &archive_filename,
+            &dirname,
+            &linux_amd64,
+            &config,
+        ));
+        assert!(rs.unwrap().ends_with("op_linux_amd64_v1.11.2"));
+        assert_eq!(
+            vec![Phase::Verifying, Phase::Unpacking],
+            *phases.lock().unwrap()
+        );
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_unpack_and_verify_bad_signature_leaves_nothing() {
+        let archive_filename: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "archives",
+            "op_linux_amd64_v1.11.2.zip",
+        ]
+        .iter()
+        .collect();
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "unpack_and_verify_signature",
+        ]
+        .iter()
+        .collect();
+        let linux_amd64 = Platform::from_str("linux_amd64").unwrap();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+
+        // the fixture ships an empty op.sig; an empty keyring of its own
+        let gnupg_home = dirname.with_extension("gnupg");
+        let _dont_care = fs::remove_dir_all(&gnupg_home);
+        assert!(fs::create_dir_all(&gnupg_home).is_ok());
+        let config = InstallConfig {
+            signature: Some(SignatureCheck {
+                public_key: None,
+                gnupg_home: Some(gnupg_home.clone()),
+                ..SignatureCheck::default()
+            }),
+            ..InstallConfig::default()
+        };
+        let rt = Runtime::new().unwrap();
+        let rs = rt.block_on(unpack_and_verify(
+            &archive_filename,
+            &dirname,
+            &linux_amd64,
+            &config,
+        ));
+        assert!(matches!(
+            rs.unwrap_err().downcast_ref::<VerificationError>(),
+            Some(VerificationError::BadSignature(_))
+        ));
+        assert_eq!(0, fs::read_dir(&dirname).unwrap().count());
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+        let _dont_care = fs::remove_dir_all(&gnupg_home);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_install_from_mirror() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_from_mirror",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+
+        let mirror = Mirror {
+            release_notes: Some("https://artifactory.corp/agilebits-updates".to_string()),
+            downloads: Some("https://artifactory.corp/agilebits-cache".to_string()),
+        };
+        // only the mirrored urls are served
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &mirror);
+        let config = InstallConfig {
+            mirror,
+            ..InstallConfig::default()
+        };
+        let req = VersionReq::parse("~1.11").unwrap();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        let release = inst.release.unwrap();
+        assert_eq!(semver::Version::new(1, 11, 4), release.version);
+        assert!(release
+            .url
+            .starts_with("https://artifactory.corp/agilebits-cache/dist/1P/op/pkg/v1.11.4/"));
+        assert!(Path::new(&inst.local_version.path)
+            .ends_with(format!("versions/1.11.4-{}/op", Platform::current())));
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_fall_back_to_installed_binary_when_unreachable() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "fall_back_to_installed_binary",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        let config = InstallConfig::default();
+        // serves nothing
+        let transport = MemoryTransport::new();
+        let rt = Runtime::new().unwrap();
+
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
+        assert!(rt.block_on(fut).is_err());
+
+        assert!(fs::File::create(dirname.join("op_linux_amd64_v1.10.3")).is_ok());
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
+        let inst = rt.block_on(fut).unwrap();
+        assert!(inst.release.is_none());
+        assert_eq!(semver::Version::new(1, 10, 3), inst.local_version.version);
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_offline_uses_local_versions_only() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "offline_local_versions_only",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        let config = InstallConfig {
+            offline: true,
+            ..InstallConfig::default()
+        };
+        // a newer release is available, but must not be looked at
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let rt = Runtime::new().unwrap();
+
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
+        let err = rt.block_on(fut).unwrap_err();
+        assert_eq!(
+            Some(&InstallError::NotInstalledOffline("*".to_string())),
+            err.downcast_ref::<InstallError>()
+        );
+
+        assert!(fs::File::create(dirname.join("op_linux_amd64_v1.10.3")).is_ok());
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
+        let inst = rt.block_on(fut).unwrap();
+        assert_eq!(semver::Version::new(1, 10, 3), inst.local_version.version);
+
+        let req = VersionReq::parse("~1.11").unwrap();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let err = rt.block_on(fut).unwrap_err();
+        assert_eq!(
+            Some(&InstallError::NotInstalledOffline(req.to_string())),
+            err.downcast_ref::<InstallError>()
+        );
+        assert_eq!(1, std::fs::read_dir(&dirname).unwrap().count());
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_concurrent_installs_are_serialized() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "concurrent_installs_are_serialized",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        // a crash left a binary in the staging directory; it's not installed
+        let staging = dirname.join(STAGING_DIRNAME);
+        assert!(fs::create_dir_all(&staging).is_ok());
+        assert!(fs::File::create(staging.join("op_linux_amd64_v1.12.3")).is_ok());
+
+        let config = InstallConfig::default();
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let rt = Runtime::new().unwrap();
+        let (l, r) = rt.block_on(async {
+            tokio::join!(
+                get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config),
+                get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config),
+            )
+        });
+        let (l, r) = (l.unwrap(), r.unwrap());
+        assert_eq!(l.local_version.path, r.local_version.path);
+        assert!(fs::metadata(&l.local_version.path).unwrap().is_file());
+
+        let mut names: Vec<_> = fs::read_dir(&dirname)
+            .unwrap()
+            .map(|ent| ent.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(vec![LOCK_FILENAME, VERSIONS_DIRNAME], names);
+        assert_eq!(1, fs::read_dir(versions_dir(&dirname)).unwrap().count());
+
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    fn test_install_version_for_foreign_platform() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_version_for_foreign_platform",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+        // built for another platform; doesn't satisfy the installation
+        assert!(fs::File::create(dirname.join("op_linux_amd64_v1.12.3")).is_ok());
+
+        let linux_arm64 = Platform::from_str("linux_arm64").unwrap();
+        let config = InstallConfig {
+            platform: Some(linux_arm64),
+            ..InstallConfig::default()
+        };
+        let req = VersionReq::parse("~1.12").unwrap();
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        assert!(inst.release.is_some());
+        assert_eq!(linux_arm64, inst.local_version.platform);
+        assert_eq!(semver::Version::new(1, 12, 3), inst.local_version.version);
+        assert!(Path::new(&inst.local_version.path).ends_with("versions/1.12.3-linux_arm64/op"));
+        assert!(fs::metadata(&inst.local_version.path).unwrap().is_file());
+
+        // installed already; nothing is downloaded
+        let empty = MemoryTransport::new();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &empty, &config);
+        assert!(rt.block_on(fut).unwrap().release.is_none());
+
+        // the same version for another platform sits next to it
+        let windows_amd64 = Platform::from_str("windows_amd64").unwrap();
+        let config = InstallConfig {
+            platform: Some(windows_amd64),
+            ..InstallConfig::default()
+        };
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let other = rt.block_on(fut).unwrap();
+        assert_eq!(windows_amd64, other.local_version.platform);
+        assert_eq!(inst.local_version.version, other.local_version.version);
+        assert!(
+            Path::new(&other.local_version.path).ends_with("versions/1.12.3-windows_amd64/op.exe")
+        );
+        for (platform, lv) in [(linux_arm64, &inst), (windows_amd64, &other)] {
+            let xs = rt
+                .block_on(find_local_versions_for(&dirname, &platform))
+                .unwrap();
+            assert_eq!(
+                vec![lv.local_version.path.clone()],
+                xs.into_iter().map(|x| x.path).collect::<Vec<_>>()
+            );
+        }
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    fn test_install_beta_version() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_beta_version",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let release_notes = fs::read_to_string(
+            testdata
+                .join("release_notes")
+                .join("2022_01_12_beta_release_notes.html"),
+        )
+        .unwrap();
+        let archive =
+            fs::read(testdata.join("archives").join("op_linux_amd64_v1.11.2.zip")).unwrap();
+        let mut transport = MemoryTransport::new();
+        for entry in parse_release_history(&release_notes).unwrap() {
+            for rl in entry.downloads {
+                transport.insert(&rl.url, archive.clone());
+            }
+        }
+        transport.insert(&ReleaseNoteUrl::V2.to_string(), release_notes.into_bytes());
+
+        let linux_amd64 = Platform::from_str("linux_amd64").unwrap();
+        let config = InstallConfig {
+            platform: Some(linux_amd64),
+            ..InstallConfig::default()
+        };
+        let req = VersionReq::parse("=2.0.0-beta.8").unwrap();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V2, &req, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        let beta = semver::Version::parse("2.0.0-beta.8").unwrap();
+        assert_eq!(beta, inst.local_version.version);
+        assert!(
+            Path::new(&inst.local_version.path).ends_with("versions/2.0.0-beta.8-linux_amd64/op")
+        );
+        let manifest = rt
+            .block_on(read_manifest(&version_dir(&dirname, &beta, &linux_amd64)))
+            .unwrap();
+        assert_eq!(Channel::Beta, manifest.channel);
+
+        // found again as the same beta
+        let empty = MemoryTransport::new();
+        let fut = install_version(&dirname, ReleaseNoteUrl::V2, &req, &empty, &config);
+        let again = rt.block_on(fut).unwrap();
+        assert!(again.release.is_none());
+        assert_eq!(beta, again.local_version.version);
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    fn test_unpack_and_verify_pkg_skips_signature() {
+        let archive_filename: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "archives",
+            "op_apple_universal_v2.1.0.pkg",
+        ]
+        .iter()
+        .collect();
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "unpack_and_verify_pkg",
+        ]
+        .iter()
+        .collect();
+        let apple_universal = Platform::from_str("apple_universal").unwrap();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+
+        // no key at all; the check would fail if it ran
+        let config = InstallConfig {
+            signature: Some(SignatureCheck {
+                public_key: None,
+                gnupg_home: Some(dirname.join("no-gnupg")),
+                ..SignatureCheck::default()
+            }),
+            ..InstallConfig::default()
+        };
+        let rt = Runtime::new().unwrap();
+        let binary_filename = rt
+            .block_on(unpack_and_verify(
+                &archive_filename,
+                &dirname,
+                &apple_universal,
+                &config,
+            ))
+            .unwrap();
+        assert_eq!(
+            b"#!/bin/sh\necho 2.1.0\n".to_vec(),
+            fs::read(&binary_filename).unwrap()
+        );
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    fn test_install_replaces_corrupt_version_dir() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_replaces_corrupt_version_dir",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        let linux_arm64 = Platform::from_str("linux_arm64").unwrap();
+        // a crash left the binary without the manifest
+        let vdir = dirname.join(VERSIONS_DIRNAME).join(version_dirname(
+            &semver::Version::new(1, 12, 3),
+            &linux_arm64,
+        ));
+        assert!(fs::create_dir_all(&vdir).is_ok());
+        assert!(fs::write(vdir.join("op"), b"half-written").is_ok());
+        assert!(fs::write(vdir.join("metadata.json"), b"{}").is_ok());
+
+        let config = InstallConfig {
+            platform: Some(linux_arm64),
+            ..InstallConfig::default()
+        };
+        let req = VersionReq::parse("~1.12").unwrap();
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let fut = install_version(&dirname, ReleaseNoteUrl::V1, &req, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        assert_eq!(vdir.join("op").to_string_lossy(), inst.local_version.path);
+        assert!(verify_installation(&inst.local_version).is_ok());
+        assert!(!vdir.join("metadata.json").exists());
+
+        // a directory with a manifest is never replaced
+        let manifest = inst.local_version.manifest.unwrap();
+        let release = Release {
+            version: semver::Version::new(1, 12, 3),
+            platform: linux_arm64,
+            url: manifest.url,
+        };
+        let staged = dirname.join("op");
+        assert!(fs::write(&staged, b"#!/bin/sh\n").is_ok());
+        let err = rt
+            .block_on(move_into_place(&staged, &staged, &dirname, &release))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<InstallError>(),
+            Some(InstallError::VersionDirTaken(_))
+        ));
+        assert!(fs::remove_dir_all(&dirname).is_ok());
+    }
+
+    #[test]
+    fn test_install_windows_binary() {
+        let dirname: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "tmp",
+            "install_windows_binary",
+        ]
+        .iter()
+        .collect();
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&dirname).is_ok());
+
+        let windows_amd64 = Platform::from_str("windows_amd64").unwrap();
+        let config = InstallConfig {
+            platform: Some(windows_amd64),
+            ..InstallConfig::default()
+        };
+        let transport = fixture_transport(&ReleaseNoteUrl::V1, &Mirror::default());
+        let fut = get_or_install(&dirname, ReleaseNoteUrl::V1, &transport, &config);
+        let rt = Runtime::new().unwrap();
+        let inst = rt.block_on(fut).unwrap();
+        assert!(
+            Path::new(&inst.local_version.path).ends_with("versions/1.12.3-windows_amd64/op.exe")
+        );
+        assert!(fs::metadata(&inst.local_version.path).unwrap().is_file());
 
+        // found locally the next time
+        let fut = find_local_versions_for(&dirname, &windows_amd64);
+        let xs = rt.block_on(fut).unwrap();
+        assert_eq!(1, xs.len());
+        assert_eq!(semver::Version::new(1, 12, 3), xs[0].version);
         assert!(fs::remove_dir_all(&dirname).is_ok());
     }
 }
@@ -0,0 +1,182 @@
+// to lay out the installed versions under the openv root:
+// - versions/<version>-<platform>/op (op.exe on windows) is the binary of the version, so
+//   that the same version can be installed for several platforms in one root;
+// - versions/<version>-<platform>/manifest.json records where it came from and its digest
+//   (see InstallManifest); the local version is read from it rather than the directory name;
+// - the binaries installed by the earlier releases of openv sit flat in the root,
+//   e.g. op_linux_amd64_v1.12.3; they are still found, but never written.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::openv::types::{LocalVersion, Platform};
+
+pub const VERSIONS_DIRNAME: &str = "versions";
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    /// the pre-releases, e.g. 2.0.0-beta.8
+    Beta,
+}
+
+impl Channel {
+    pub fn of(version: &Version) -> Self {
+        if version.pre.is_empty() {
+            Channel::Stable
+        } else {
+            Channel::Beta
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// written next to the binary when it is installed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallManifest {
+    pub version: Version,
+    pub platform: Platform,
+    /// where the archive is downloaded from (after the mirror rewrite)
+    pub url: String,
+    /// the sha-256 digest (hex) of the binary
+    pub sha256: String,
+    /// the sha-256 digest (hex) of the downloaded archive
+    pub archive_sha256: String,
+    /// seconds since the unix epoch
+    pub installed_at: u64,
+    pub channel: Channel,
+}
+
+pub fn versions_dir(dirname: &Path) -> PathBuf {
+    dirname.join(VERSIONS_DIRNAME)
+}
+
+/// the directory name of the version built for the platform, e.g. 2.1.0-linux_amd64
+pub fn version_dirname(version: &Version, platform: &Platform) -> String {
+    format!("{}-{}", version, platform)
+}
+
+pub fn version_dir(dirname: &Path, version: &Version, platform: &Platform) -> PathBuf {
+    versions_dir(dirname).join(version_dirname(version, platform))
+}
+
+pub async fn read_manifest(version_dir: &Path) -> anyhow::Result<InstallManifest> {
+    let text = fs::read_to_string(version_dir.join(MANIFEST_FILENAME)).await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+pub async fn write_manifest(version_dir: &Path, manifest: &InstallManifest) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(manifest)?;
+    fs::write(version_dir.join(MANIFEST_FILENAME), text).await?;
+    Ok(())
+}
+
+/// the versions installed under versions/; a directory without a (readable) manifest is
+/// skipped, and replaced when its version is installed again
+pub async fn find_versioned(dirname: &Path) -> anyhow::Result<Vec<LocalVersion>> {
+    let dir = versions_dir(dirname);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = fs::read_dir(&dir).await?;
+    let mut xs = Vec::new();
+    while let Some(ent) = entries.next_entry().await? {
+        let manifest = match read_manifest(&ent.path()).await {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        xs.push(LocalVersion {
+            version: manifest.version.clone(),
+            platform: manifest.platform,
+            path: ent
+                .path()
+                .join(manifest.platform.binary_name())
+                .to_string_lossy()
+                .into_owned(),
+            manifest: Some(manifest),
+        });
+    }
+    Ok(xs)
+}
+
+/// remove the version directory, or the flat binary of the earlier layout
+pub async fn remove_installation(lv: &LocalVersion) -> anyhow::Result<()> {
+    let binary = Path::new(&lv.path);
+    match binary.parent() {
+        Some(dir) if lv.manifest.is_some() => fs::remove_dir_all(dir).await?,
+        _ => fs::remove_file(binary).await?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn test_channel_of_version() {
+        assert_eq!(Channel::Stable, Channel::of(&Version::new(2, 1, 0)));
+        let beta = Version::parse("2.0.0-beta.8").unwrap();
+        assert_eq!(Channel::Beta, Channel::of(&beta));
+        assert_eq!("\"beta\"", serde_json::to_string(&Channel::Beta).unwrap());
+    }
+
+    #[test]
+    fn test_find_versioned_and_remove() {
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("layout_find_versioned");
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        let linux = Platform::from_str("linux_amd64").unwrap();
+        let manifest = InstallManifest {
+            version: Version::new(2, 1, 0),
+            platform: linux,
+            url: "https://cache.agilebits.com/dist/1P/op2/pkg/v2.1.0/op_linux_amd64_v2.1.0.zip"
+                .to_string(),
+            sha256: "e3b0c442".to_string(),
+            archive_sha256: "7a2bc2f3".to_string(),
+            installed_at: 1_637_000_000,
+            channel: Channel::Stable,
+        };
+        let rt = Runtime::new().unwrap();
+        let vdir = version_dir(&dirname, &manifest.version, &linux);
+        std::fs::create_dir_all(&vdir).unwrap();
+        std::fs::File::create(vdir.join("op")).unwrap();
+        rt.block_on(write_manifest(&vdir, &manifest)).unwrap();
+        assert!(vdir.ends_with("versions/2.1.0-linux_amd64"));
+        // not an installation
+        std::fs::create_dir_all(versions_dir(&dirname).join("2.0.0")).unwrap();
+
+        let xs = rt.block_on(find_versioned(&dirname)).unwrap();
+        assert_eq!(1, xs.len());
+        let lv = &xs[0];
+        assert_eq!(Version::new(2, 1, 0), lv.version);
+        assert_eq!(linux, lv.platform);
+        assert_eq!(vdir.join("op").to_string_lossy(), lv.path);
+        assert_eq!(Some(&manifest), lv.manifest.as_ref());
+
+        rt.block_on(remove_installation(lv)).unwrap();
+        assert!(!vdir.exists());
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+}
@@ -1,30 +1,65 @@
 use std::path::Path;
 use std::str::FromStr;
 
+use semver::{Version, VersionReq};
 use thiserror::Error;
 use tokio::fs;
 
+use crate::openv::layout::{find_versioned, remove_installation};
 use crate::openv::types::*;
+use crate::openv::version_file::{selected_version_with, VERSION_ENV};
 
 #[derive(Debug, PartialEq, Error)]
 enum LocalVersionError {
     #[error("can't find any local versions.")]
     NoLocalVersion,
+
+    #[error("can't find any local version matching: {0}")]
+    NoMatchingLocalVersion(String),
+
+    #[error("version {0} is not installed.")]
+    NotInstalled(Version),
 }
 
-pub async fn find_local_version(dirname: &Path) -> anyhow::Result<LocalVersion> {
-    use LocalVersionError::*;
+/// all the local versions in the directory that are built for the current platform
+pub async fn find_local_versions(dirname: &Path) -> anyhow::Result<Vec<LocalVersion>> {
+    find_local_versions_for(dirname, &Platform::current()).await
+}
+
+/// all the local versions in the directory that are built for the platform
+pub async fn find_local_versions_for(
+    dirname: &Path,
+    platform: &Platform,
+) -> anyhow::Result<Vec<LocalVersion>> {
     let mut dir = fs::read_dir(dirname).await?;
-    let mut xs = Vec::with_capacity(1024);
-    let cp = Platform::current();
+    let mut xs: Vec<LocalVersion> = find_versioned(dirname)
+        .await?
+        .into_iter()
+        .filter(|lv| &lv.platform == platform)
+        .collect();
+    // the flat binaries of the earlier layout
     while let Some(ent) = dir.next_entry().await? {
         let s = &ent.path().to_string_lossy().into_owned();
         if let Ok(lv) = LocalVersion::from_str(s) {
-            if lv.platform == cp {
+            if &lv.platform == platform && !xs.iter().any(|x| x.version == lv.version) {
                 xs.push(lv);
             }
         }
     }
+    Ok(xs)
+}
+
+pub async fn find_local_version(dirname: &Path) -> anyhow::Result<LocalVersion> {
+    find_local_version_for(dirname, &Platform::current()).await
+}
+
+/// the newest local version built for the platform
+pub async fn find_local_version_for(
+    dirname: &Path,
+    platform: &Platform,
+) -> anyhow::Result<LocalVersion> {
+    use LocalVersionError::*;
+    let xs = find_local_versions_for(dirname, platform).await?;
     let opt_max = xs.into_iter().max_by(|l, r| l.version.cmp(&r.version));
     match opt_max {
         Some(mx) => Ok(mx),
@@ -32,6 +67,92 @@ pub async fn find_local_version(dirname: &Path) -> anyhow::Result<LocalVersion>
     }
 }
 
+/// the newest local version that satisfies the version requirement
+pub async fn find_matching_local_version(
+    dirname: &Path,
+    req: &VersionReq,
+) -> anyhow::Result<LocalVersion> {
+    find_matching_local_version_for(dirname, req, &Platform::current()).await
+}
+
+/// the newest local version built for the platform that satisfies the version requirement
+pub async fn find_matching_local_version_for(
+    dirname: &Path,
+    req: &VersionReq,
+    platform: &Platform,
+) -> anyhow::Result<LocalVersion> {
+    use LocalVersionError::*;
+    let xs = find_local_versions_for(dirname, platform).await?;
+    let opt_max = xs
+        .into_iter()
+        .filter(|lv| req.matches(&lv.version))
+        .max_by(|l, r| l.version.cmp(&r.version));
+    match opt_max {
+        Some(mx) => Ok(mx),
+        None => Err(anyhow::Error::new(NoMatchingLocalVersion(req.to_string()))),
+    }
+}
+
+/// the local versions of the current platform, newest first
+pub async fn list_installed(dirname: &Path) -> anyhow::Result<Vec<LocalVersion>> {
+    let mut xs = find_local_versions(dirname).await?;
+    xs.sort_by(|l, r| r.version.cmp(&l.version));
+    Ok(xs)
+}
+
+/// remove the installation of the version; return what is removed
+pub async fn uninstall(dirname: &Path, version: &Version) -> anyhow::Result<LocalVersion> {
+    use LocalVersionError::*;
+    let lv = find_local_versions(dirname)
+        .await?
+        .into_iter()
+        .find(|lv| &lv.version == version)
+        .ok_or_else(|| NotInstalled(version.clone()))?;
+    remove_installation(&lv).await?;
+    Ok(lv)
+}
+
+/// remove all but the newest `keep_latest` versions; the version selected in the directory
+/// `cwd` (by the env var, .op-version or the global version file) and a version that matches
+/// any of the pinned requirements are never removed; return what is removed
+pub async fn prune(
+    dirname: &Path,
+    cwd: &Path,
+    keep_latest: usize,
+    pinned: &[VersionReq],
+) -> anyhow::Result<Vec<LocalVersion>> {
+    prune_with(
+        dirname,
+        cwd,
+        keep_latest,
+        pinned,
+        std::env::var(VERSION_ENV).ok(),
+    )
+    .await
+}
+
+async fn prune_with(
+    dirname: &Path,
+    cwd: &Path,
+    keep_latest: usize,
+    pinned: &[VersionReq],
+    env: Option<String>,
+) -> anyhow::Result<Vec<LocalVersion>> {
+    let mut pinned = pinned.to_vec();
+    if let Some((req, _)) = selected_version_with(dirname, cwd, env).await? {
+        pinned.push(req);
+    }
+    let mut removed = Vec::new();
+    for lv in list_installed(dirname).await?.into_iter().skip(keep_latest) {
+        if pinned.iter().any(|req| req.matches(&lv.version)) {
+            continue;
+        }
+        remove_installation(&lv).await?;
+        removed.push(lv);
+    }
+    Ok(removed)
+}
+
 #[cfg(test)]
 mod test {
     #[allow(unused_imports)]
@@ -54,6 +175,7 @@ mod test {
             version,
             platform,
             path,
+            ..
         } = lv.unwrap();
         assert_eq!(Version::new(1, 11, 2), version);
         assert_eq!(
@@ -66,6 +188,24 @@ mod test {
         assert!(path.ends_with("op_linux_amd64_v1.11.2"));
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_find_matching_local_version() {
+        let p = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("fake_binaries");
+        let req = VersionReq::parse("~1.10").unwrap();
+        let fut = find_matching_local_version(&p, &req);
+        let rt = Runtime::new().unwrap();
+        let lv = rt.block_on(fut).unwrap();
+        assert_eq!(Version::new(1, 10, 9), lv.version);
+        assert!(lv.path.ends_with("op_linux_amd64_v1.10.9"));
+
+        let req = VersionReq::parse("^2").unwrap();
+        let fut = find_matching_local_version(&p, &req);
+        assert!(rt.block_on(fut).is_err());
+    }
+
     #[test]
     fn test_not_find_local_version() {
         let p = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
@@ -74,4 +214,85 @@ mod test {
         let lv = rt.block_on(fut);
         assert!(lv.is_err());
     }
+
+    #[cfg(target_os = "linux")]
+    fn make_installed(name: &str, versions: &[&str]) -> std::path::PathBuf {
+        let p = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join(name);
+        let _dont_care = std::fs::remove_dir_all(&p);
+        std::fs::create_dir_all(&p).unwrap();
+        for v in versions {
+            std::fs::File::create(p.join(format!("op_linux_amd64_v{}", v))).unwrap();
+        }
+        // another platform is left alone
+        std::fs::File::create(p.join("op_windows_amd64_v1.0.0.exe")).unwrap();
+        p
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_list_installed_and_uninstall() {
+        let p = make_installed(
+            "list_installed_and_uninstall",
+            &["1.10.3", "1.12.1", "1.9.0"],
+        );
+        let rt = Runtime::new().unwrap();
+        let xs = rt.block_on(list_installed(&p)).unwrap();
+        let versions: Vec<_> = xs.iter().map(|lv| lv.version.to_string()).collect();
+        assert_eq!(vec!["1.12.1", "1.10.3", "1.9.0"], versions);
+
+        let lv = rt.block_on(uninstall(&p, &Version::new(1, 10, 3))).unwrap();
+        assert!(lv.path.ends_with("op_linux_amd64_v1.10.3"));
+        assert!(!Path::new(&lv.path).exists());
+        let err = rt
+            .block_on(uninstall(&p, &Version::new(1, 10, 3)))
+            .unwrap_err();
+        assert_eq!(
+            Some(&LocalVersionError::NotInstalled(Version::new(1, 10, 3))),
+            err.downcast_ref::<LocalVersionError>()
+        );
+        std::fs::remove_dir_all(&p).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_prune_keeps_latest_and_pinned() {
+        let p = make_installed(
+            "prune_keeps_latest_and_pinned",
+            &["1.9.0", "1.10.3", "1.11.2", "1.12.1", "1.12.3"],
+        );
+        let rt = Runtime::new().unwrap();
+        let windows = Platform {
+            os: OperatingSystem::Windows,
+            arch: Arch::AMD64,
+        };
+        let foreign = rt.block_on(find_local_versions_for(&p, &windows)).unwrap();
+        assert_eq!(1, foreign.len());
+
+        // the project in cwd uses 1.9
+        let cwd = p.join("project");
+        std::fs::create_dir_all(&cwd).unwrap();
+        std::fs::write(cwd.join(".op-version"), "~1.9\n").unwrap();
+        let pinned = vec![VersionReq::parse("=1.10.3").unwrap()];
+        let removed = rt.block_on(prune_with(&p, &cwd, 2, &pinned, None)).unwrap();
+        let removed: Vec<_> = removed.iter().map(|lv| lv.version.to_string()).collect();
+        assert_eq!(vec!["1.11.2"], removed);
+
+        let left = rt.block_on(list_installed(&p)).unwrap();
+        let left: Vec<_> = left.iter().map(|lv| lv.version.to_string()).collect();
+        assert_eq!(vec!["1.12.3", "1.12.1", "1.10.3", "1.9.0"], left);
+        assert_eq!(
+            foreign,
+            rt.block_on(find_local_versions_for(&p, &windows)).unwrap()
+        );
+
+        // the env var wins over .op-version
+        let env = Some("=1.10.3".to_string());
+        let removed = rt.block_on(prune_with(&p, &cwd, 2, &[], env)).unwrap();
+        let removed: Vec<_> = removed.iter().map(|lv| lv.version.to_string()).collect();
+        assert_eq!(vec!["1.9.0"], removed);
+        std::fs::remove_dir_all(&p).unwrap();
+    }
 }
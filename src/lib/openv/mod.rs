@@ -1,3 +1,5 @@
+mod cpio;
+
 #[allow(dead_code)]
 mod downloader;
 
@@ -7,16 +9,53 @@ mod local_versions;
 #[allow(dead_code)]
 mod op_release;
 
+mod progress;
+
+mod release_cache;
+
+mod remote_versions;
+
+mod transport;
+
 #[allow(dead_code)]
 mod types;
 
 mod home_dir;
 mod installer;
+mod layout;
 mod settings;
+mod shim;
 #[allow(dead_code)]
 mod unpacker;
+mod verifier;
+
+mod version_file;
+
+mod xar;
 
-pub use home_dir::get_or_create;
-pub use installer::get_or_install;
-pub use settings::ReleaseNoteUrl;
-pub use types::Installation;
+pub use home_dir::{get_or_create, get_or_create_in, root_dir, ROOT_ENV};
+pub use installer::{fetch_release_history, get_or_install, install_version, InstallError};
+pub use layout::{Channel, InstallManifest, MANIFEST_FILENAME, VERSIONS_DIRNAME};
+pub use local_versions::{list_installed, prune, uninstall};
+pub use op_release::parse_release_history;
+pub use progress::{Phase, Progress, ProgressEvent, ProgressObserver};
+pub use release_cache::{ReleaseNotesCache, DEFAULT_RELEASE_NOTES_TTL};
+pub use remote_versions::{
+    list_remote_versions, list_remote_versions_marked, mark_remote_versions, RemoteVersion,
+};
+pub use settings::{InstallConfig, Mirror, ReleaseNoteUrl};
+pub use shim::{init_script, shims_dir, write_shim, Shell, SHIMS_DIRNAME};
+pub use transport::{
+    Body, Conditional, ContentKind, FsTransport, MemoryTransport, ReqwestTransport, Transport,
+    TransportError, TransportResponse, Validators,
+};
+pub use types::{Installation, LocalVersion, Platform, Release, ReleaseEntry};
+pub use verifier::{
+    verify_installation, SignatureCheck, VerificationError, ONE_PASSWORD_PUBLIC_KEY,
+    ONE_PASSWORD_SIGNING_KEY,
+};
+pub use version_file::{
+    find_local_version_file, global_version_file, parse_version_pin, resolve_version,
+    selected_version, write_version_file, ResolvedVersion, VersionSource, GLOBAL_VERSION_FILENAME,
+    LOCAL_VERSION_FILENAME, VERSION_ENV,
+};
@@ -6,8 +6,10 @@
 //   linux: { 386: ..., AMD64: ... }
 //   ...
 // }
+// to extract the full release history (one entry per <article>) with the
+// version, release date, download urls and changelog of each release.
 
-use crate::openv::settings::ReleaseNoteUrl;
+use crate::openv::transport::Transport;
 use crate::openv::types::*;
 use regex::Regex;
 use std::str::FromStr;
@@ -21,6 +23,20 @@ fn extract_latest_release(text: &str) -> anyhow::Result<&str> {
     Ok(latest_release_info)
 }
 
+fn extract_all_releases(text: &str) -> anyhow::Result<Vec<&str>> {
+    use HtmlParsingError::*;
+    let (_, html_body) = text.split_once("<body>").ok_or(MissingBodyTag)?;
+    let releases = html_body
+        .split("</article>")
+        .filter(|release_info| release_info.contains("<article"))
+        .collect::<Vec<_>>();
+    if releases.is_empty() {
+        Err(anyhow::Error::new(MissingArticleTag))
+    } else {
+        Ok(releases)
+    }
+}
+
 fn extract_download_urls(text: &str) -> anyhow::Result<Vec<&str>> {
     use HtmlParsingError::*;
     let url_re = Regex::new(r####" href="(https.+?)" title="####).unwrap();
@@ -35,28 +51,123 @@ fn extract_download_urls(text: &str) -> anyhow::Result<Vec<&str>> {
     }
 }
 
-fn parse_download_urls(urls: Vec<&str>) -> anyhow::Result<Release> {
+fn extract_version(text: &str) -> anyhow::Result<semver::Version> {
+    let version_re = Regex::new(&format!(r"<h3>\s*({})", VERSION_PATTERN)).unwrap();
+    let version_str = version_re
+        .captures(text)
+        .and_then(|cap| cap.get(1))
+        .ok_or_else(|| anyhow::anyhow!("missing release version"))?
+        .as_str();
+    parse_version(version_str)
+}
+
+fn extract_release_date(text: &str) -> String {
+    let date_re = Regex::new(r"released ([0-9]{4}-[0-9]{2}-[0-9]{2})").unwrap();
+    date_re
+        .captures(text)
+        .and_then(|cap| cap.get(1))
+        .map(|mat| mat.as_str().to_string())
+        .unwrap_or_default()
+}
+
+/// the text after the downloads section (or after the heading if there are no downloads);
+/// block elements become lines, list items become "- ..." lines and inline tags are dropped
+fn extract_changelog(text: &str) -> String {
+    let (_, rest) = text.split_once("</h3>").unwrap_or(("", text));
+    let rest = match rest.split_once("class=\"cli-archs\"") {
+        Some((_, archs)) => archs.split_once("</div>").map_or(archs, |(_, x)| x),
+        None => rest,
+    };
+    let li_re = Regex::new(r"<li[^>]*>").unwrap();
+    let block_re = Regex::new(r"</?(p|h[1-6]|ul|ol|li|div|br)\b[^>]*>").unwrap();
+    let tag_re = Regex::new(r"<[^>]+>").unwrap();
+    let with_bullets = li_re.replace_all(rest, "\n- ");
+    let with_blocks = block_re.replace_all(&with_bullets, "\n");
+    let plain = tag_re.replace_all(&with_blocks, "");
+    plain
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty() && line != "-")
+        .fold(Vec::<String>::new(), |mut lines, line| {
+            // a bullet followed by its text on the next line
+            match lines.last_mut() {
+                Some(last) if last == "-" => last.push_str(&format!(" {}", line)),
+                _ => lines.push(line),
+            }
+            lines
+        })
+        .join("\n")
+}
+
+fn parse_release_entry(text: &str) -> anyhow::Result<ReleaseEntry> {
+    let downloads = extract_download_urls(text)
+        .map(|urls| {
+            urls.into_iter()
+                .filter_map(|url| Release::from_str(url).ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    Ok(ReleaseEntry {
+        version: extract_version(text)?,
+        release_date: extract_release_date(text),
+        downloads,
+        changelog: extract_changelog(text),
+    })
+}
+
+fn parse_download_urls(urls: Vec<&str>, platform: &Platform) -> anyhow::Result<Release> {
     use HtmlParsingError::*;
-    let cp = Platform::current();
     for url in urls {
         if let Ok(rl) = Release::from_str(url) {
-            if rl.platform == cp {
+            if &rl.platform == platform {
                 return Ok(rl);
             }
         }
     }
-    Err(anyhow::Error::new(MissingPlatform(cp)))
+    Err(anyhow::Error::new(MissingPlatform(*platform)))
 }
 
-pub fn parse_release_notes(body: &str) -> anyhow::Result<Release> {
+/// the latest release for the platform
+pub fn parse_release_notes(body: &str, platform: &Platform) -> anyhow::Result<Release> {
     let latest_release_info = extract_latest_release(body)?;
     let download_urls = extract_download_urls(latest_release_info)?;
-    parse_download_urls(download_urls)
+    parse_download_urls(download_urls, platform)
 }
 
-pub async fn download_release_notes(release_note_url: &ReleaseNoteUrl) -> anyhow::Result<String> {
-    let resp = reqwest::get(release_note_url.to_string()).await?;
-    resp.text().await.map_err(anyhow::Error::new)
+/// every release on the page (newest first), regardless of the platform
+pub fn parse_release_history(body: &str) -> anyhow::Result<Vec<ReleaseEntry>> {
+    Ok(extract_all_releases(body)?
+        .into_iter()
+        .filter_map(|release_info| parse_release_entry(release_info).ok())
+        .collect::<Vec<_>>())
+}
+
+/// every release (newest first) that ships a binary for the platform
+pub fn parse_all_releases(body: &str, platform: &Platform) -> anyhow::Result<Vec<Release>> {
+    let releases = parse_release_history(body)?
+        .iter()
+        .filter_map(|entry| entry.download_for(platform).cloned())
+        .collect::<Vec<_>>();
+    if releases.is_empty() {
+        Err(anyhow::Error::new(HtmlParsingError::MissingPlatform(
+            *platform,
+        )))
+    } else {
+        Ok(releases)
+    }
+}
+
+pub async fn download_release_notes(
+    transport: &dyn Transport,
+    release_note_url: &str,
+) -> anyhow::Result<String> {
+    transport.get_text(release_note_url).await
 }
 
 #[cfg(test)]
@@ -72,10 +183,114 @@ mod test {
             .join("release_notes")
             .join("2021_11_14_release_notes.html");
         let release_notes = read_to_string(filename).unwrap();
-        let rl = parse_release_notes(&release_notes).unwrap();
+        let rl = parse_release_notes(&release_notes, &Platform::current()).unwrap();
         assert_eq!(semver::Version::new(1, 12, 3), rl.version);
     }
 
+    #[test]
+    fn test_parse_release_notes_for_foreign_platform() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("release_notes")
+            .join("2021_11_14_release_notes.html");
+        let release_notes = read_to_string(filename).unwrap();
+        let freebsd_arm = Platform::from_str("freebsd_arm").unwrap();
+        let rl = parse_release_notes(&release_notes, &freebsd_arm).unwrap();
+        assert_eq!(freebsd_arm, rl.platform);
+        assert!(rl.url.ends_with("op_freebsd_arm_v1.12.3.zip"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse_all_releases_expect_successful() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("release_notes")
+            .join("2021_11_14_release_notes.html");
+        let release_notes = read_to_string(filename).unwrap();
+        let releases = parse_all_releases(&release_notes, &Platform::current()).unwrap();
+        assert_eq!(semver::Version::new(1, 12, 3), releases[0].version);
+        let req = semver::VersionReq::parse("~1.10").unwrap();
+        let rl = releases
+            .iter()
+            .filter(|rl| req.matches(&rl.version))
+            .max_by(|l, r| l.version.cmp(&r.version))
+            .unwrap();
+        assert_eq!(semver::Version::new(1, 10, 3), rl.version);
+    }
+
+    #[test]
+    fn test_parse_release_history_expect_successful() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("release_notes")
+            .join("2021_11_14_release_notes.html");
+        let release_notes = read_to_string(filename).unwrap();
+        let history = parse_release_history(&release_notes).unwrap();
+        assert_eq!(58, history.len());
+
+        let latest = &history[0];
+        assert_eq!(semver::Version::new(1, 12, 3), latest.version);
+        assert_eq!("2021-10-27", latest.release_date);
+        assert_eq!(14, latest.downloads.len());
+        let linux_amd64 = Platform::from_str("linux_amd64").unwrap();
+        assert!(latest
+            .download_for(&linux_amd64)
+            .unwrap()
+            .url
+            .ends_with("op_linux_amd64_v1.12.3.zip"));
+        assert!(latest
+            .changelog
+            .starts_with("This release contains a bugfix"));
+        assert!(latest
+            .changelog
+            .contains("\nFixed\n- Retrieving items when using --format csv"));
+
+        // abbreviated version
+        let v06 = history
+            .iter()
+            .find(|entry| entry.version == semver::Version::new(0, 6, 0))
+            .unwrap();
+        assert_eq!("2019-08-23", v06.release_date);
+        assert!(v06.download_for(&linux_amd64).is_some());
+
+        // early releases without any downloads
+        let oldest = history.last().unwrap();
+        assert_eq!(semver::Version::new(0, 0, 1), oldest.version);
+        assert!(oldest.downloads.is_empty());
+    }
+
+    #[test]
+    fn test_parse_beta_release_history() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("release_notes")
+            .join("2022_01_12_beta_release_notes.html");
+        let release_notes = read_to_string(filename).unwrap();
+        let history = parse_release_history(&release_notes).unwrap();
+        assert_eq!(2, history.len());
+        let beta = semver::Version::parse("2.0.0-beta.8").unwrap();
+        assert_eq!(beta, history[0].version);
+        assert_eq!("2022-01-12", history[0].release_date);
+        assert!(history[0].downloads.iter().all(|rl| rl.version == beta));
+        assert_eq!(semver::Version::new(1, 12, 3), history[1].version);
+    }
+
+    #[test]
+    fn test_extract_all_releases_missing_article_tag() {
+        let release_notes = r##"<body>
+        </body>"##;
+        let result = extract_all_releases(release_notes);
+        assert!(result.is_err());
+        assert_eq!(
+            &HtmlParsingError::MissingArticleTag,
+            result
+                .unwrap_err()
+                .downcast_ref::<HtmlParsingError>()
+                .unwrap()
+        );
+    }
+
     #[test]
     fn test_extract_latest_release_missing_body_tag() {
         let release_notes = r##"something fake"##;
@@ -122,7 +337,7 @@ mod test {
     #[test]
     fn test_parse_download_urls_no_base() {
         let urls = vec!["http"];
-        let result = parse_download_urls(urls);
+        let result = parse_download_urls(urls, &Platform::current());
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -133,7 +348,7 @@ mod test {
     #[test]
     fn test_parse_download_urls_missing_os_arch() {
         let urls = vec!["https://some/v123.zip"];
-        let result = parse_download_urls(urls);
+        let result = parse_download_urls(urls, &Platform::current());
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -144,7 +359,7 @@ mod test {
     #[test]
     fn test_parse_download_urls_invalid_os() {
         let urls = vec!["https://some/op_snes_16bit_v122.zip"];
-        let result = parse_download_urls(urls);
+        let result = parse_download_urls(urls, &Platform::current());
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -155,7 +370,7 @@ mod test {
     #[test]
     fn test_parse_download_urls_invalid_arch() {
         let urls = vec!["https://some/op_linux_16bit_v122.zip"];
-        let result = parse_download_urls(urls);
+        let result = parse_download_urls(urls, &Platform::current());
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::sync::Arc;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    FetchingReleaseNotes,
+    Downloading,
+    Verifying,
+    Unpacking,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// the installation enters a new phase
+    Phase(Phase),
+    /// bytes received (downloading) or written (unpacking) so far, against the total if known
+    Bytes { received: u64, total: Option<u64> },
+    /// the binary is ready to use
+    Completed { path: String },
+}
+
+/// receives the progress events of an installation; implemented for closures and
+/// for the sending half of a tokio channel
+pub trait ProgressObserver: Send + Sync {
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressObserver for F
+where
+    F: Fn(&ProgressEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+impl ProgressObserver for UnboundedSender<ProgressEvent> {
+    fn on_event(&self, event: &ProgressEvent) {
+        // the receiver may have gone away; the installation carries on regardless
+        let _dont_care = self.send(event.clone());
+    }
+}
+
+/// an optional, cheaply cloneable observer; the default one reports nothing
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<dyn ProgressObserver>>);
+
+impl Progress {
+    pub fn new<T: ProgressObserver + 'static>(observer: T) -> Self {
+        Self(Some(Arc::new(observer)))
+    }
+
+    pub fn report(&self, event: ProgressEvent) {
+        if let Some(observer) = &self.0 {
+            observer.on_event(&event);
+        }
+    }
+
+    pub fn phase(&self, phase: Phase) {
+        self.report(ProgressEvent::Phase(phase));
+    }
+
+    pub fn bytes(&self, received: u64, total: Option<u64>) {
+        self.report(ProgressEvent::Bytes { received, total });
+    }
+
+    pub fn completed(&self, path: &str) {
+        self.report(ProgressEvent::Completed {
+            path: path.to_string(),
+        });
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(_) => write!(f, "Progress(observer)"),
+            None => write!(f, "Progress(none)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_report_to_closure() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let progress = Progress::new(move |ev: &ProgressEvent| {
+            recorded.lock().unwrap().push(ev.clone());
+        });
+        progress.phase(Phase::Downloading);
+        progress.bytes(10, Some(20));
+        progress.completed("/tmp/op");
+        assert_eq!(
+            vec![
+                ProgressEvent::Phase(Phase::Downloading),
+                ProgressEvent::Bytes {
+                    received: 10,
+                    total: Some(20)
+                },
+                ProgressEvent::Completed {
+                    path: "/tmp/op".to_string()
+                },
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_report_to_channel() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let progress = Progress::new(tx);
+        progress.phase(Phase::Unpacking);
+        drop(progress);
+        assert_eq!(
+            Some(ProgressEvent::Phase(Phase::Unpacking)),
+            rx.blocking_recv()
+        );
+        assert_eq!(None, rx.blocking_recv());
+    }
+
+    #[test]
+    fn test_report_nothing_by_default() {
+        Progress::default().bytes(1, None);
+    }
+}
@@ -0,0 +1,269 @@
+// to cache the parsed release history under the openv home directory:
+// - a fresh cache (younger than the TTL) is used without touching the network;
+// - a stale cache is revalidated with the ETag/Last-Modified of the previous response;
+// - a stale cache is still used if the release notes can't be fetched, and it is the only
+//   source when offline (see cached_release_history).
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::openv::op_release::parse_release_history;
+use crate::openv::settings::ReleaseNoteUrl;
+use crate::openv::transport::{Conditional, Transport, Validators};
+use crate::openv::types::ReleaseEntry;
+
+pub const DEFAULT_RELEASE_NOTES_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseNotesCache {
+    pub dirname: PathBuf,
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    /// seconds since the unix epoch
+    fetched_at: u64,
+    validators: Validators,
+    releases: Vec<ReleaseEntry>,
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ReleaseNotesCache {
+    pub fn new(dirname: &Path, ttl: Duration) -> Self {
+        Self {
+            dirname: dirname.to_path_buf(),
+            ttl,
+        }
+    }
+
+    fn filename(&self, release_note_url: &ReleaseNoteUrl) -> PathBuf {
+        let name = match release_note_url {
+            ReleaseNoteUrl::V1 => "release_notes_v1.json",
+            ReleaseNoteUrl::V2 => "release_notes_v2.json",
+        };
+        self.dirname.join(name)
+    }
+
+    /// the cached entry of the same url, if any
+    async fn load(&self, release_note_url: &ReleaseNoteUrl, url: &str) -> Option<CacheEntry> {
+        let text = fs::read_to_string(self.filename(release_note_url))
+            .await
+            .ok()?;
+        serde_json::from_str::<CacheEntry>(&text)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    async fn save(
+        &self,
+        release_note_url: &ReleaseNoteUrl,
+        entry: &CacheEntry,
+    ) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dirname).await?;
+        fs::write(
+            self.filename(release_note_url),
+            serde_json::to_string(entry)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// the cached release history of the (mirrored) release notes url, however old;
+    /// the network is never touched
+    pub async fn cached_release_history(
+        &self,
+        release_note_url: &ReleaseNoteUrl,
+        url: &str,
+    ) -> Option<Vec<ReleaseEntry>> {
+        self.load(release_note_url, url)
+            .await
+            .map(|entry| entry.releases)
+    }
+
+    /// the release history of the (mirrored) release notes url, through the cache
+    pub async fn release_history(
+        &self,
+        transport: &dyn Transport,
+        release_note_url: &ReleaseNoteUrl,
+        url: &str,
+    ) -> anyhow::Result<Vec<ReleaseEntry>> {
+        let cached = self.load(release_note_url, url).await;
+        if let Some(entry) = &cached {
+            if now_secs().saturating_sub(entry.fetched_at) < self.ttl.as_secs() {
+                return Ok(cached.unwrap().releases);
+            }
+        }
+        let validators = cached
+            .as_ref()
+            .map(|entry| entry.validators.clone())
+            .unwrap_or_default();
+        let entry = match (
+            transport.get_text_if_modified(url, &validators).await,
+            cached,
+        ) {
+            (Ok(Conditional::NotModified), Some(entry)) => CacheEntry {
+                fetched_at: now_secs(),
+                ..entry
+            },
+            (Ok(Conditional::Modified { text, validators }), _) => CacheEntry {
+                url: url.to_string(),
+                fetched_at: now_secs(),
+                validators,
+                releases: parse_release_history(&text)?,
+            },
+            (Ok(Conditional::NotModified), None) => {
+                return Err(anyhow::anyhow!(
+                    "not modified, but nothing is cached: {}",
+                    url
+                ))
+            }
+            // stale, but better than nothing
+            (Err(_), Some(entry)) => return Ok(entry.releases),
+            (Err(e), None) => return Err(e),
+        };
+        self.save(release_note_url, &entry).await?;
+        Ok(entry.releases)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::openv::transport::{MemoryTransport, ReqwestTransport};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    fn release_notes() -> String {
+        std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("release_notes")
+                .join("2021_11_14_release_notes.html"),
+        )
+        .unwrap()
+    }
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join(name);
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        std::fs::create_dir_all(&dirname).unwrap();
+        dirname
+    }
+
+    #[test]
+    fn test_fresh_cache_skips_the_network() {
+        let rt = Runtime::new().unwrap();
+        let dirname = tmp_dir("release_cache_fresh");
+        let cache = ReleaseNotesCache::new(&dirname, DEFAULT_RELEASE_NOTES_TTL);
+        let url = ReleaseNoteUrl::V1.to_string();
+        let transport = MemoryTransport::new().with(&url, release_notes().into_bytes());
+        let fetched = rt
+            .block_on(cache.release_history(&transport, &ReleaseNoteUrl::V1, &url))
+            .unwrap();
+        assert_eq!(58, fetched.len());
+
+        // nothing is served any more
+        let offline = MemoryTransport::new();
+        let cached = rt
+            .block_on(cache.release_history(&offline, &ReleaseNoteUrl::V1, &url))
+            .unwrap();
+        assert_eq!(fetched, cached);
+
+        // another url (e.g. a mirror) is a cache miss
+        let rs = rt.block_on(cache.release_history(&offline, &ReleaseNoteUrl::V1, "https://x"));
+        assert!(rs.is_err());
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+
+    #[test]
+    fn test_stale_cache_is_used_when_offline() {
+        let rt = Runtime::new().unwrap();
+        let dirname = tmp_dir("release_cache_stale");
+        let cache = ReleaseNotesCache::new(&dirname, Duration::from_secs(0));
+        let url = ReleaseNoteUrl::V2.to_string();
+        let transport = MemoryTransport::new().with(&url, release_notes().into_bytes());
+        let fetched = rt
+            .block_on(cache.release_history(&transport, &ReleaseNoteUrl::V2, &url))
+            .unwrap();
+        let offline = MemoryTransport::new();
+        let cached = rt
+            .block_on(cache.release_history(&offline, &ReleaseNoteUrl::V2, &url))
+            .unwrap();
+        assert_eq!(fetched, cached);
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+
+    /// serves the release notes with an ETag; answers 304 if the ETag matches
+    async fn serve_with_etag(body: String, hits: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/product_history/CLI",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut req = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !req.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    req.extend_from_slice(&buf[..n]);
+                }
+                hits.fetch_add(1, Ordering::SeqCst);
+                let req = String::from_utf8_lossy(&req).to_lowercase();
+                let resp = if req.contains("if-none-match: \"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                let _dont_care = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_stale_cache_is_revalidated() {
+        let rt = Runtime::new().unwrap();
+        let dirname = tmp_dir("release_cache_revalidated");
+        let cache = ReleaseNotesCache::new(&dirname, Duration::from_secs(0));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let url = rt.block_on(serve_with_etag(release_notes(), hits.clone()));
+        let transport = ReqwestTransport::default();
+        let fetched = rt
+            .block_on(cache.release_history(&transport, &ReleaseNoteUrl::V1, &url))
+            .unwrap();
+        let revalidated = rt
+            .block_on(cache.release_history(&transport, &ReleaseNoteUrl::V1, &url))
+            .unwrap();
+        assert_eq!(2, hits.load(Ordering::SeqCst));
+        assert_eq!(fetched, revalidated);
+        let saved = std::fs::read_to_string(dirname.join("release_notes_v1.json")).unwrap();
+        assert!(saved.contains("\\\"v1\\\""));
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+}
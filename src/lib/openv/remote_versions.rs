@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use semver::Version;
+
+use crate::openv::installer::fetch_release_history;
+use crate::openv::local_versions::find_local_versions_for;
+use crate::openv::settings::{InstallConfig, ReleaseNoteUrl};
+use crate::openv::transport::Transport;
+use crate::openv::types::{LocalVersion, Platform, ReleaseEntry};
+
+/// a released version, marked against the local versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteVersion {
+    pub version: Version,
+    pub platforms: Vec<Platform>,
+    /// installed for the current platform
+    pub installed: bool,
+    /// the newest version released for the current platform
+    pub newest: bool,
+}
+
+/// the released versions (newest first) and the platforms each is built for
+pub async fn list_remote_versions(
+    release_note_url: ReleaseNoteUrl,
+    transport: &dyn Transport,
+    config: &InstallConfig,
+) -> anyhow::Result<Vec<(Version, Vec<Platform>)>> {
+    let history = fetch_release_history(&release_note_url, transport, config).await?;
+    Ok(versions_of(&history))
+}
+
+fn versions_of(history: &[ReleaseEntry]) -> Vec<(Version, Vec<Platform>)> {
+    history
+        .iter()
+        .map(|entry| {
+            let mut platforms: Vec<Platform> = Vec::with_capacity(entry.downloads.len());
+            // e.g. a pkg and a zip of the same platform
+            for rl in &entry.downloads {
+                if !platforms.contains(&rl.platform) {
+                    platforms.push(rl.platform);
+                }
+            }
+            (entry.version.clone(), platforms)
+        })
+        .collect()
+}
+
+/// mark the versions that are installed, and the newest one for the platform
+pub fn mark_remote_versions(
+    versions: Vec<(Version, Vec<Platform>)>,
+    installed: &[LocalVersion],
+    platform: &Platform,
+) -> Vec<RemoteVersion> {
+    let newest = versions
+        .iter()
+        .filter(|(_, platforms)| platforms.contains(platform))
+        .map(|(v, _)| v)
+        .max()
+        .cloned();
+    versions
+        .into_iter()
+        .map(|(version, platforms)| RemoteVersion {
+            installed: installed
+                .iter()
+                .any(|lv| lv.version == version && &lv.platform == platform),
+            newest: newest.as_ref() == Some(&version),
+            version,
+            platforms,
+        })
+        .collect()
+}
+
+/// the released versions, marked against the versions installed in the directory
+pub async fn list_remote_versions_marked(
+    dirname: &Path,
+    release_note_url: ReleaseNoteUrl,
+    transport: &dyn Transport,
+    config: &InstallConfig,
+) -> anyhow::Result<Vec<RemoteVersion>> {
+    let versions = list_remote_versions(release_note_url, transport, config).await?;
+    let platform = config.target_platform();
+    let installed = find_local_versions_for(dirname, &platform).await?;
+    Ok(mark_remote_versions(versions, &installed, &platform))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::openv::transport::MemoryTransport;
+
+    fn fixture_transport() -> MemoryTransport {
+        let release_notes = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("release_notes")
+                .join("2021_11_14_release_notes.html"),
+        )
+        .unwrap();
+        MemoryTransport::new().with(&ReleaseNoteUrl::V1.to_string(), release_notes)
+    }
+
+    #[test]
+    fn test_list_remote_versions() {
+        let rt = Runtime::new().unwrap();
+        let config = InstallConfig::default();
+        let transport = fixture_transport();
+        let fut = list_remote_versions(ReleaseNoteUrl::V1, &transport, &config);
+        let versions = rt.block_on(fut).unwrap();
+        assert_eq!(58, versions.len());
+        let (latest, platforms) = &versions[0];
+        assert_eq!(&Version::new(1, 12, 3), latest);
+        assert!(platforms.contains(&Platform::from_str("linux_amd64").unwrap()));
+        assert!(platforms.contains(&Platform::from_str("apple_universal").unwrap()));
+    }
+
+    #[test]
+    fn test_list_remote_versions_offline() {
+        use crate::openv::installer::InstallError;
+        use crate::openv::release_cache::{ReleaseNotesCache, DEFAULT_RELEASE_NOTES_TTL};
+
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("list_remote_versions_offline");
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        let rt = Runtime::new().unwrap();
+        let cached = InstallConfig {
+            release_notes_cache: Some(ReleaseNotesCache::new(&dirname, DEFAULT_RELEASE_NOTES_TTL)),
+            ..InstallConfig::default()
+        };
+        let offline = InstallConfig {
+            offline: true,
+            ..cached.clone()
+        };
+        let transport = fixture_transport();
+        // every request fails
+        let unreachable = MemoryTransport::new();
+
+        let fut = list_remote_versions(ReleaseNoteUrl::V1, &transport, &offline);
+        let err = rt.block_on(fut).unwrap_err();
+        assert_eq!(
+            Some(&InstallError::NotCachedOffline(
+                ReleaseNoteUrl::V1.to_string()
+            )),
+            err.downcast_ref::<InstallError>()
+        );
+
+        // cached while online, then served from the cache only
+        let fut = list_remote_versions(ReleaseNoteUrl::V1, &transport, &cached);
+        let online = rt.block_on(fut).unwrap();
+        let fut = list_remote_versions(ReleaseNoteUrl::V1, &unreachable, &offline);
+        assert_eq!(online, rt.block_on(fut).unwrap());
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+
+    #[test]
+    fn test_mark_remote_versions() {
+        let linux = Platform::from_str("linux_amd64").unwrap();
+        let bsd = Platform::from_str("openbsd_arm64").unwrap();
+        let versions = vec![
+            (Version::new(1, 12, 3), vec![bsd]),
+            (Version::new(1, 12, 2), vec![linux, bsd]),
+            (Version::new(1, 12, 1), vec![linux]),
+        ];
+        let installed = vec![
+            LocalVersion::from_str("/x/op_linux_amd64_v1.12.1").unwrap(),
+            LocalVersion::from_str("/x/op_openbsd_arm64_v1.12.3").unwrap(),
+        ];
+        let marked = mark_remote_versions(versions, &installed, &linux);
+        let flags: Vec<_> = marked.iter().map(|rv| (rv.installed, rv.newest)).collect();
+        assert_eq!(vec![(false, false), (false, true), (true, false)], flags);
+    }
+}
@@ -1,3 +1,11 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::openv::progress::Progress;
+use crate::openv::release_cache::ReleaseNotesCache;
+use crate::openv::types::{Platform, Release};
+use crate::openv::verifier::SignatureCheck;
+
 #[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub enum ReleaseNoteUrl {
@@ -5,15 +13,303 @@ pub enum ReleaseNoteUrl {
     V2,
 }
 
-impl ToString for ReleaseNoteUrl {
-    fn to_string(&self) -> String {
+pub const RELEASE_NOTES_ORIGIN: &str = "https://app-updates.agilebits.com";
+pub const DOWNLOAD_ORIGIN: &str = "https://cache.agilebits.com";
+
+impl ReleaseNoteUrl {
+    /// 0.x and 1.x versions are listed in the v1 release notes, the rest in the v2 ones
+    pub fn for_version(version: &semver::Version) -> Self {
+        match version.major {
+            0 | 1 => ReleaseNoteUrl::V1,
+            _ => ReleaseNoteUrl::V2,
+        }
+    }
+
+    /// the v1 release notes if the requirement rules out 2.0.0 and above, e.g. ~1.12 or <2;
+    /// the v2 ones otherwise, e.g. >=1.5 or *
+    pub fn for_requirement(req: &semver::VersionReq) -> Self {
+        if req.comparators.iter().any(below_v2) {
+            ReleaseNoteUrl::V1
+        } else {
+            ReleaseNoteUrl::V2
+        }
+    }
+
+    /// the path relative to the release notes origin (or mirror)
+    pub fn path(&self) -> &'static str {
         match self {
-            ReleaseNoteUrl::V1 => {
-                "https://app-updates.agilebits.com/product_history/CLI".to_owned()
+            ReleaseNoteUrl::V1 => "/product_history/CLI",
+            ReleaseNoteUrl::V2 => "/product_history/CLI2",
+        }
+    }
+}
+
+/// whether the comparator only matches the versions below 2.0.0
+fn below_v2(c: &semver::Comparator) -> bool {
+    use semver::Op;
+    match c.op {
+        Op::Exact | Op::LessEq | Op::Tilde | Op::Caret | Op::Wildcard => c.major <= 1,
+        Op::Less => (c.major, c.minor.unwrap_or(0), c.patch.unwrap_or(0)) <= (2, 0, 0),
+        _ => false,
+    }
+}
+
+impl fmt::Display for ReleaseNoteUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", RELEASE_NOTES_ORIGIN, self.path())
+    }
+}
+
+/// replaces the origins of the release notes and the binaries, e.g. with an internal
+/// proxy such as https://artifactory.corp/artifactory/agilebits-cache;
+/// the binary filename (op_<os>_<arch>_v<version>) is kept intact
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mirror {
+    /// replaces https://app-updates.agilebits.com
+    pub release_notes: Option<String>,
+    /// replaces https://cache.agilebits.com
+    pub downloads: Option<String>,
+}
+
+impl Mirror {
+    pub const RELEASE_NOTES_ENV: &'static str = "OPENV_RELEASE_NOTES_MIRROR";
+    pub const DOWNLOADS_ENV: &'static str = "OPENV_DOWNLOAD_MIRROR";
+    /// the name of the config file in the openv home directory
+    pub const CONFIG_FILENAME: &'static str = "mirror.conf";
+
+    /// read the config file in the home directory (if any), then let the env vars override it
+    pub fn load(home_dir: &Path) -> anyhow::Result<Self> {
+        let filename = home_dir.join(Self::CONFIG_FILENAME);
+        let from_file = if filename.is_file() {
+            Self::parse_config(&std::fs::read_to_string(&filename)?)?
+        } else {
+            Self::default()
+        };
+        Ok(from_file.overridden_by(Self::from_env()))
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_lookup(|k| std::env::var(k).ok())
+    }
+
+    fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Self {
+        let non_empty = |k| lookup(k).filter(|v: &String| !v.trim().is_empty());
+        Self {
+            release_notes: non_empty(Self::RELEASE_NOTES_ENV),
+            downloads: non_empty(Self::DOWNLOADS_ENV),
+        }
+    }
+
+    /// the config file consists of `key = value` lines; the keys are
+    /// `release_notes` and `downloads`; `#` starts a comment
+    pub fn parse_config(text: &str) -> anyhow::Result<Self> {
+        let mut mirror = Self::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
             }
-            ReleaseNoteUrl::V2 => {
-                "https://app-updates.agilebits.com/product_history/CLI2".to_owned()
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("invalid mirror config line: {}", line))?;
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "release_notes" => mirror.release_notes = value,
+                "downloads" => mirror.downloads = value,
+                other => return Err(anyhow::anyhow!("unknown mirror config key: {}", other)),
             }
         }
+        Ok(mirror)
+    }
+
+    fn overridden_by(self, other: Self) -> Self {
+        Self {
+            release_notes: other.release_notes.or(self.release_notes),
+            downloads: other.downloads.or(self.downloads),
+        }
+    }
+
+    pub fn release_notes_url(&self, release_note_url: &ReleaseNoteUrl) -> String {
+        match &self.release_notes {
+            Some(base) => format!("{}{}", base.trim_end_matches('/'), release_note_url.path()),
+            None => release_note_url.to_string(),
+        }
+    }
+
+    /// point the release to the mirror
+    pub fn rewrite(&self, release: Release) -> Release {
+        Release {
+            url: self.download_url(&release.url),
+            ..release
+        }
+    }
+
+    pub fn download_url(&self, url: &str) -> String {
+        match (&self.downloads, url.strip_prefix(DOWNLOAD_ORIGIN)) {
+            (Some(base), Some(path)) => format!("{}{}", base.trim_end_matches('/'), path),
+            _ => url.to_string(),
+        }
+    }
+}
+
+/// how to install a release; the default config skips all the verifications
+#[derive(Debug, Default, Clone)]
+pub struct InstallConfig {
+    /// the expected sha-256 digest (hex) of the downloaded archive, e.g. pinned along with the version
+    pub sha256: Option<String>,
+    /// verify the binary against the signature (op.sig) shipped in the archive
+    pub signature: Option<SignatureCheck>,
+    /// receives the phases and the download/unpack progress of the installation
+    pub progress: Progress,
+    /// where to fetch the release notes and the binaries from
+    pub mirror: Mirror,
+    /// cache the parsed release notes; None always downloads them
+    pub release_notes_cache: Option<ReleaseNotesCache>,
+    /// never touch the network; only the local versions are used
+    pub offline: bool,
+    /// the platform to install the binaries for; None is the current platform
+    pub platform: Option<Platform>,
+}
+
+impl InstallConfig {
+    pub const OFFLINE_ENV: &'static str = "OPENV_OFFLINE";
+
+    pub fn target_platform(&self) -> Platform {
+        self.platform.unwrap_or_else(Platform::current)
+    }
+
+    /// whether OPENV_OFFLINE is set to 1, true, yes or on
+    pub fn offline_from_env() -> bool {
+        Self::offline_from_lookup(|k| std::env::var(k).ok())
+    }
+
+    fn offline_from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> bool {
+        lookup(Self::OFFLINE_ENV)
+            .map(|v| {
+                matches!(
+                    v.trim().to_lowercase().as_str(),
+                    "1" | "true" | "yes" | "on"
+                )
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_default_mirror_keeps_urls() {
+        let mirror = Mirror::default();
+        assert_eq!(
+            "https://app-updates.agilebits.com/product_history/CLI2",
+            mirror.release_notes_url(&ReleaseNoteUrl::V2)
+        );
+        let u = "https://cache.agilebits.com/dist/1P/op/pkg/v1.12.3/op_linux_amd64_v1.12.3.zip";
+        assert_eq!(u, mirror.download_url(u));
+    }
+
+    #[test]
+    fn test_mirror_rewrites_urls() {
+        let mirror = Mirror {
+            release_notes: Some("https://artifactory.corp/agilebits-updates/".to_string()),
+            downloads: Some("https://artifactory.corp/agilebits-cache".to_string()),
+        };
+        assert_eq!(
+            "https://artifactory.corp/agilebits-updates/product_history/CLI",
+            mirror.release_notes_url(&ReleaseNoteUrl::V1)
+        );
+        let u = mirror.download_url(
+            "https://cache.agilebits.com/dist/1P/op/pkg/v1.12.3/op_linux_amd64_v1.12.3.zip",
+        );
+        assert_eq!(
+            "https://artifactory.corp/agilebits-cache/dist/1P/op/pkg/v1.12.3/op_linux_amd64_v1.12.3.zip",
+            u
+        );
+        let rl = Release::from_str(&u).unwrap();
+        assert_eq!(semver::Version::new(1, 12, 3), rl.version);
+        // urls of other origins are left alone
+        assert_eq!("https://x/op.zip", mirror.download_url("https://x/op.zip"));
+    }
+
+    #[test]
+    fn test_mirror_from_config_and_env() {
+        let text = r##"
+        # corporate proxy
+        release_notes = https://proxy/updates
+        downloads = https://proxy/cache
+        "##;
+        let from_file = Mirror::parse_config(text).unwrap();
+        assert_eq!(Some("https://proxy/cache".to_string()), from_file.downloads);
+        assert!(Mirror::parse_config("mirror https://proxy").is_err());
+        assert!(Mirror::parse_config("upstream = https://proxy").is_err());
+
+        let from_env = Mirror::from_lookup(|k| {
+            (k == Mirror::DOWNLOADS_ENV).then(|| "https://env/cache".to_string())
+        });
+        let mirror = from_file.overridden_by(from_env);
+        assert_eq!(
+            Some("https://proxy/updates".to_string()),
+            mirror.release_notes
+        );
+        assert_eq!(Some("https://env/cache".to_string()), mirror.downloads);
+    }
+
+    #[test]
+    fn test_offline_from_env() {
+        let lookup = |v: &'static str| {
+            move |k: &str| (k == InstallConfig::OFFLINE_ENV).then(|| v.to_string())
+        };
+        assert!(InstallConfig::offline_from_lookup(lookup("1")));
+        assert!(InstallConfig::offline_from_lookup(lookup(" True ")));
+        assert!(!InstallConfig::offline_from_lookup(lookup("0")));
+        assert!(!InstallConfig::offline_from_lookup(lookup("")));
+        assert!(!InstallConfig::offline_from_lookup(|_| None));
+    }
+
+    #[test]
+    fn test_release_note_url_for_version() {
+        assert_eq!(
+            ReleaseNoteUrl::V1,
+            ReleaseNoteUrl::for_version(&semver::Version::new(1, 12, 3))
+        );
+        assert_eq!(
+            ReleaseNoteUrl::V1,
+            ReleaseNoteUrl::for_version(&semver::Version::new(0, 9, 4))
+        );
+        assert_eq!(
+            ReleaseNoteUrl::V2,
+            ReleaseNoteUrl::for_version(&semver::Version::new(2, 0, 0))
+        );
+        let req = |s| semver::VersionReq::parse(s).unwrap();
+        assert_eq!(
+            ReleaseNoteUrl::V1,
+            ReleaseNoteUrl::for_requirement(&req("~1.11"))
+        );
+        assert_eq!(
+            ReleaseNoteUrl::V1,
+            ReleaseNoteUrl::for_requirement(&req("^0.9"))
+        );
+        assert_eq!(
+            ReleaseNoteUrl::V2,
+            ReleaseNoteUrl::for_requirement(&req("=2.4"))
+        );
+        assert_eq!(
+            ReleaseNoteUrl::V2,
+            ReleaseNoteUrl::for_requirement(&req("*"))
+        );
+        for (r, url) in [
+            ("^1", ReleaseNoteUrl::V1),
+            ("<2", ReleaseNoteUrl::V1),
+            ("<=1.12", ReleaseNoteUrl::V1),
+            (">=1.5, <2", ReleaseNoteUrl::V1),
+            (">=1.5", ReleaseNoteUrl::V2),
+            (">1", ReleaseNoteUrl::V2),
+            ("<2.1", ReleaseNoteUrl::V2),
+        ] {
+            assert_eq!(url, ReleaseNoteUrl::for_requirement(&req(r)), "{}", r);
+        }
     }
 }
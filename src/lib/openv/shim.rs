@@ -0,0 +1,157 @@
+// to make a plain `op` on PATH run the version selected by openv:
+// - `openv init` writes an `op` shim to the shims directory, which runs `openv exec`;
+// - `openv exec` resolves the version (see version_file.rs) and execs the binary;
+// - `eval "$(openv init -)"` puts the shims directory in front of PATH.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tokio::fs;
+
+pub const SHIMS_DIRNAME: &str = "shims";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// guess the shell from $SHELL; bash if unknown
+    pub fn from_env() -> Self {
+        std::env::var("SHELL")
+            .ok()
+            .and_then(|s| {
+                let name = Path::new(&s).file_name()?.to_string_lossy().into_owned();
+                Self::from_str(&name).ok()
+            })
+            .unwrap_or(Shell::Bash)
+    }
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(anyhow::anyhow!("unsupported shell: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn shims_dir(dirname: &Path) -> PathBuf {
+    dirname.join(SHIMS_DIRNAME)
+}
+
+#[cfg(target_family = "unix")]
+fn shim_script(openv: &Path) -> (&'static str, String) {
+    let script = format!(
+        "#!/bin/sh\n# generated by openv; runs the op version selected by openv\nexec \"{}\" exec -- \"$@\"\n",
+        openv.display()
+    );
+    ("op", script)
+}
+
+#[cfg(target_family = "windows")]
+fn shim_script(openv: &Path) -> (&'static str, String) {
+    let script = format!(
+        "@echo off\r\nrem generated by openv; runs the op version selected by openv\r\n\"{}\" exec -- %*\r\n",
+        openv.display()
+    );
+    ("op.cmd", script)
+}
+
+#[cfg(target_family = "unix")]
+async fn make_executable(p: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(p, std::fs::Permissions::from_mode(0o755)).await
+}
+
+#[cfg(target_family = "windows")]
+async fn make_executable(_p: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// write the `op` shim that runs `<openv> exec -- <args>`, so that openv never takes the
+/// arguments (e.g. --json) as its own; return the shim filename
+pub async fn write_shim(dirname: &Path, openv: &Path) -> anyhow::Result<PathBuf> {
+    let dir = shims_dir(dirname);
+    fs::create_dir_all(&dir).await?;
+    let (name, script) = shim_script(openv);
+    let filename = dir.join(name);
+    fs::write(&filename, script).await?;
+    make_executable(&filename).await?;
+    Ok(filename)
+}
+
+/// the shell integration printed by `openv init -`
+pub fn init_script(shell: Shell, shims_dir: &Path) -> String {
+    let dir = shims_dir.display();
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("export PATH=\"{}:${{PATH}}\"\n", dir),
+        Shell::Fish => format!("set -gx PATH \"{}\" $PATH\n", dir),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn test_init_script() {
+        let dir = Path::new("/home/u/.op_cli/shims");
+        assert_eq!(
+            "export PATH=\"/home/u/.op_cli/shims:${PATH}\"\n",
+            init_script(Shell::Zsh, dir)
+        );
+        assert_eq!(
+            "set -gx PATH \"/home/u/.op_cli/shims\" $PATH\n",
+            init_script(Shell::Fish, dir)
+        );
+        assert_eq!(Shell::Fish, Shell::from_str("fish").unwrap());
+        assert!(Shell::from_str("tcsh").is_err());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_shim_passes_arguments_through() {
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("shim_passes_arguments_through");
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        let rt = Runtime::new().unwrap();
+        // echo stands in for openv
+        let shim = rt
+            .block_on(write_shim(&dirname, Path::new("/bin/echo")))
+            .unwrap();
+        assert_eq!(shims_dir(&dirname).join("op"), shim);
+        let out = std::process::Command::new(&shim)
+            .args(["--json", "item", "get", "a b"])
+            .output()
+            .unwrap();
+        assert_eq!(
+            "exec -- --json item get a b\n",
+            String::from_utf8_lossy(&out.stdout)
+        );
+        std::fs::remove_dir_all(&dirname).unwrap();
+    }
+}
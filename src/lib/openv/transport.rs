@@ -0,0 +1,476 @@
+// the http transport behind the release notes and the downloader:
+// - ReqwestTransport talks to the real servers (or an http mirror);
+// - FsTransport serves a mirrored directory tree: <root>/<host>/<path>;
+// - MemoryTransport serves a url -> bytes map, e.g. for the tests.
+// Every transport answers range requests with a Partial response if it supports them.
+// Only ReqwestTransport revalidates (ETag/Last-Modified) and honours If-Range; the others
+// report no validators and always report the content as modified.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use reqwest::header::{
+    HeaderMap, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+    RANGE,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum TransportError {
+    #[error("request has been rejected: {url} (status: {status})")]
+    Rejected { url: String, status: u16 },
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("invalid range {start}-{end} of {url} (size: {size})")]
+    InvalidRange {
+        url: String,
+        start: u64,
+        end: u64,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    /// the whole content; the range (if any) has been ignored
+    Full { length: Option<u64> },
+    /// the requested range of the content of the total size
+    Partial { total: u64 },
+}
+
+#[async_trait]
+pub trait Body: Send {
+    /// the next chunk of the body, None at the end
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>>;
+}
+
+pub struct TransportResponse {
+    pub kind: ContentKind,
+    /// the validators of the content, if the server sends any
+    pub validators: Validators,
+    pub body: Box<dyn Body>,
+}
+
+/// the cache validators of a previous response
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// the value for If-Range: the etag, or the last modified date
+    pub fn if_range(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Conditional {
+    NotModified,
+    Modified {
+        text: String,
+        validators: Validators,
+    },
+}
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// GET the url; the range is inclusive at both ends
+    async fn get(&self, url: &str, range: Option<(u64, u64)>) -> anyhow::Result<TransportResponse>;
+
+    /// GET the range if the content still matches the If-Range value (an etag or a date),
+    /// the whole content otherwise
+    async fn get_if_range(
+        &self,
+        url: &str,
+        range: (u64, u64),
+        _if_range: &str,
+    ) -> anyhow::Result<TransportResponse> {
+        self.get(url, Some(range)).await
+    }
+
+    async fn get_text(&self, url: &str) -> anyhow::Result<String> {
+        let mut res = self.get(url, None).await?;
+        let mut buf = Vec::new();
+        while let Some(chunk) = res.body.chunk().await? {
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// GET the text unless it still matches the validators
+    async fn get_text_if_modified(
+        &self,
+        url: &str,
+        _validators: &Validators,
+    ) -> anyhow::Result<Conditional> {
+        Ok(Conditional::Modified {
+            text: self.get_text(url).await?,
+            validators: Validators::default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+struct ReqwestBody(reqwest::Response);
+
+#[async_trait]
+impl Body for ReqwestBody {
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.chunk().await?.map(|x| x.to_vec()))
+    }
+}
+
+impl ReqwestTransport {
+    async fn send(
+        &self,
+        url: &str,
+        range: Option<(u64, u64)>,
+        if_range: Option<&str>,
+    ) -> anyhow::Result<TransportResponse> {
+        let mut req = self.client.get(url);
+        if let Some((start, end)) = range {
+            req = req.header(RANGE, format!("bytes={}-{}", start, end));
+        }
+        if let Some(if_range) = if_range {
+            req = req.header(IF_RANGE, if_range);
+        }
+        let res = req.send().await?;
+        let kind = match res.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let total = res
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit_once('/'))
+                    .and_then(|(_, total)| total.parse::<u64>().ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("missing total size in the content range: {}", url)
+                    })?;
+                ContentKind::Partial { total }
+            }
+            StatusCode::OK => ContentKind::Full {
+                length: res.content_length(),
+            },
+            status => {
+                return Err(anyhow::Error::new(TransportError::Rejected {
+                    url: url.to_string(),
+                    status: status.as_u16(),
+                }))
+            }
+        };
+        Ok(TransportResponse {
+            kind,
+            validators: Validators::from_headers(res.headers()),
+            body: Box::new(ReqwestBody(res)),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn get(&self, url: &str, range: Option<(u64, u64)>) -> anyhow::Result<TransportResponse> {
+        self.send(url, range, None).await
+    }
+
+    async fn get_if_range(
+        &self,
+        url: &str,
+        range: (u64, u64),
+        if_range: &str,
+    ) -> anyhow::Result<TransportResponse> {
+        self.send(url, Some(range), Some(if_range)).await
+    }
+
+    async fn get_text_if_modified(
+        &self,
+        url: &str,
+        validators: &Validators,
+    ) -> anyhow::Result<Conditional> {
+        let mut req = self.client.get(url);
+        if let Some(etag) = &validators.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let res = req.send().await?;
+        match res.status() {
+            StatusCode::NOT_MODIFIED => Ok(Conditional::NotModified),
+            StatusCode::OK => {
+                let validators = Validators::from_headers(res.headers());
+                Ok(Conditional::Modified {
+                    text: res.text().await?,
+                    validators,
+                })
+            }
+            status => Err(anyhow::Error::new(TransportError::Rejected {
+                url: url.to_string(),
+                status: status.as_u16(),
+            })),
+        }
+    }
+}
+
+/// validate the inclusive range against the size; the end is clamped to the last byte
+fn clamp_range(url: &str, range: (u64, u64), size: u64) -> anyhow::Result<(u64, u64)> {
+    let (start, end) = range;
+    if start > end || start >= size {
+        return Err(anyhow::Error::new(TransportError::InvalidRange {
+            url: url.to_string(),
+            start,
+            end,
+            size,
+        }));
+    }
+    Ok((start, end.min(size - 1)))
+}
+
+/// serves the files of a mirrored directory tree,
+/// e.g. https://cache.agilebits.com/dist/x.zip -> <root>/cache.agilebits.com/dist/x.zip
+#[derive(Debug, Clone)]
+pub struct FsTransport {
+    root: PathBuf,
+}
+
+impl FsTransport {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    fn path_of(&self, url: &str) -> anyhow::Result<PathBuf> {
+        let without_scheme = url.split_once("://").map_or(url, |(_, x)| x);
+        let without_query = without_scheme.split(['?', '#']).next().unwrap_or_default();
+        let relative = Path::new(without_query);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow::Error::new(TransportError::NotFound(
+                url.to_string(),
+            )));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+struct FileBody {
+    file: tokio::fs::File,
+    remaining: u64,
+}
+
+#[async_trait]
+impl Body for FileBody {
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0u8; CHUNK_SIZE.min(self.remaining as usize)];
+        let n = self.file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        self.remaining -= n as u64;
+        Ok(Some(buf))
+    }
+}
+
+#[async_trait]
+impl Transport for FsTransport {
+    async fn get(&self, url: &str, range: Option<(u64, u64)>) -> anyhow::Result<TransportResponse> {
+        let path = self.path_of(url)?;
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|_| TransportError::NotFound(url.to_string()))?;
+        let size = file.metadata().await?.len();
+        let (kind, remaining) = match range {
+            Some(r) => {
+                let (start, end) = clamp_range(url, r, size)?;
+                file.seek(std::io::SeekFrom::Start(start)).await?;
+                (ContentKind::Partial { total: size }, end - start + 1)
+            }
+            None => (ContentKind::Full { length: Some(size) }, size),
+        };
+        Ok(TransportResponse {
+            kind,
+            validators: Validators::default(),
+            body: Box::new(FileBody { file, remaining }),
+        })
+    }
+}
+
+/// serves the registered url -> content map
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransport {
+    contents: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, url: &str, content: Vec<u8>) -> Self {
+        self.insert(url, content);
+        self
+    }
+
+    pub fn insert(&mut self, url: &str, content: Vec<u8>) {
+        self.contents.insert(url.to_string(), content);
+    }
+}
+
+struct MemoryBody {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+#[async_trait]
+impl Body for MemoryBody {
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+        let end = (self.pos + CHUNK_SIZE).min(self.data.len());
+        let chunk = self.data[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(Some(chunk))
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn get(&self, url: &str, range: Option<(u64, u64)>) -> anyhow::Result<TransportResponse> {
+        let content = self
+            .contents
+            .get(url)
+            .ok_or_else(|| TransportError::NotFound(url.to_string()))?;
+        let size = content.len() as u64;
+        let (kind, data) = match range {
+            Some(r) => {
+                let (start, end) = clamp_range(url, r, size)?;
+                (
+                    ContentKind::Partial { total: size },
+                    content[start as usize..=end as usize].to_vec(),
+                )
+            }
+            None => (ContentKind::Full { length: Some(size) }, content.clone()),
+        };
+        Ok(TransportResponse {
+            kind,
+            validators: Validators::default(),
+            body: Box::new(MemoryBody { data, pos: 0 }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    async fn read_all(res: TransportResponse) -> Vec<u8> {
+        let mut body = res.body;
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            buf.extend_from_slice(&chunk);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_memory_transport_serves_ranges() {
+        let rt = Runtime::new().unwrap();
+        let content = (0..200 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let transport = MemoryTransport::new().with("https://x/op.zip", content.clone());
+
+        let res = rt
+            .block_on(transport.get("https://x/op.zip", None))
+            .unwrap();
+        assert_eq!(
+            ContentKind::Full {
+                length: Some(content.len() as u64)
+            },
+            res.kind
+        );
+        assert_eq!(content, rt.block_on(read_all(res)));
+
+        let res = rt
+            .block_on(transport.get("https://x/op.zip", Some((10, 19))))
+            .unwrap();
+        assert_eq!(
+            ContentKind::Partial {
+                total: content.len() as u64
+            },
+            res.kind
+        );
+        assert_eq!(&content[10..20], &rt.block_on(read_all(res))[..]);
+
+        let rs = rt.block_on(transport.get("https://x/op.zip", Some((1 << 30, 1 << 31))));
+        assert!(rs.is_err());
+        let rs = rt.block_on(transport.get("https://x/other.zip", None));
+        assert!(matches!(
+            rs.err().unwrap().downcast_ref::<TransportError>(),
+            Some(TransportError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_fs_transport_serves_mirrored_tree() {
+        let rt = Runtime::new().unwrap();
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let transport = FsTransport::new(&root);
+        let text = rt
+            .block_on(transport.get_text("https://release_notes/2021_11_14_release_notes.html"))
+            .unwrap();
+        assert!(text.contains("<article"));
+
+        let res = rt
+            .block_on(transport.get(
+                "https://archives/op_linux_amd64_v1.11.2.zip?query=1",
+                Some((0, 1)),
+            ))
+            .unwrap();
+        assert!(matches!(res.kind, ContentKind::Partial { .. }));
+        assert_eq!(b"PK".to_vec(), rt.block_on(read_all(res)));
+
+        assert!(rt
+            .block_on(transport.get("https://archives/../../Cargo.toml", None))
+            .is_err());
+    }
+}
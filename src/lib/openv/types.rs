@@ -1,13 +1,16 @@
 use std::env;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::str::FromStr;
 
+use crate::openv::layout::InstallManifest;
 use crate::ReleaseNoteUrl;
 use anyhow::anyhow;
 use regex::Regex;
 use semver::Version;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Error)]
 pub enum HtmlParsingError {
     #[error("Missing html <body>...</body> tag.")]
@@ -23,7 +26,7 @@ pub enum HtmlParsingError {
     MissingPlatform(Platform),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Release {
     pub version: Version,
     pub platform: Platform,
@@ -33,18 +36,23 @@ pub struct Release {
 impl FromStr for Release {
     type Err = anyhow::Error;
 
-    /// Expect: https://.../op_<os>_<arch>_v<version>*
+    /// Expect: https://.../op_<os>_<arch>_v<version>*, where the version may be a pre-release,
+    /// e.g. v2.0.0-beta.8
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let base = match s.rsplit_once("/") {
             Some((_, x)) => x,
             None => s,
         };
-        let re = Regex::new("^op_([0-9a-zA-Z]+_[0-9a-zA-Z]+)_v([0-9.]+[0-9])").unwrap();
+        let re = Regex::new(&format!(
+            "^op_([0-9a-zA-Z]+_[0-9a-zA-Z]+)_v({})",
+            VERSION_PATTERN
+        ))
+        .unwrap();
         let captures = re
             .captures(base)
             .ok_or_else(|| anyhow!("invalid format: {}", base))?;
         let platform = Platform::from_str(captures.get(1).unwrap().as_str())?;
-        let version = Version::from_str(captures.get(2).unwrap().as_str())?;
+        let version = parse_version(captures.get(2).unwrap().as_str())?;
         Ok(Release {
             version,
             platform,
@@ -53,31 +61,78 @@ impl FromStr for Release {
     }
 }
 
+/// a (possibly abbreviated) release version with the optional pre-release, e.g. 0.6 or
+/// 2.0.0-beta.8; the pre-release identifiers after the first are numeric, so that the file
+/// extension is not taken for one
+pub(crate) const VERSION_PATTERN: &str = r"[0-9]+(?:\.[0-9]+)*(?:-[0-9A-Za-z]+(?:\.[0-9]+)*)?";
+
+/// parse a (possibly abbreviated) release version, e.g. "0.6" is read as "0.6.0"
+pub fn parse_version(s: &str) -> anyhow::Result<Version> {
+    let num_parts = s.split('.').count();
+    let padded = match num_parts {
+        1 => format!("{}.0.0", s),
+        2 => format!("{}.0", s),
+        _ => s.to_string(),
+    };
+    Version::from_str(&padded).map_err(anyhow::Error::new)
+}
+
+/// one release (one <article>) in the release notes
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ReleaseEntry {
+    pub version: Version,
+    /// e.g. 2021-10-27
+    pub release_date: String,
+    /// the download urls of all the recognizable platforms
+    pub downloads: Vec<Release>,
+    /// the plain-text description and the list of changes
+    pub changelog: String,
+}
+
+impl ReleaseEntry {
+    pub fn download_for(&self, platform: &Platform) -> Option<&Release> {
+        self.downloads.iter().find(|rl| &rl.platform == platform)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct LocalVersion {
     pub version: Version,
     pub platform: Platform,
     pub path: String,
+    /// None for the binaries of the earlier (flat) layout
+    pub manifest: Option<InstallManifest>,
 }
 
 impl FromStr for LocalVersion {
     type Err = anyhow::Error;
 
+    /// Expect: .../op_<os>_<arch>_v<version>, or .../op_windows_<arch>_v<version>.exe
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let basename = match s.rsplit_once(|ch| ch == '/' || ch == '\\') {
+        let basename = match s.rsplit_once(['/', '\\']) {
             Some((_, x)) => x,
             None => s,
         };
+        // not an archive or a signature left behind
+        let re = Regex::new(&format!(
+            r"^op_[0-9a-zA-Z]+_[0-9a-zA-Z]+_v{}(\.exe)?$",
+            VERSION_PATTERN
+        ))
+        .unwrap();
+        if !re.is_match(basename) {
+            return Err(anyhow!("not a binary: {}", basename));
+        }
         let rl = Release::from_str(basename)?;
         Ok(LocalVersion {
             version: rl.version,
             platform: rl.platform,
             path: s.to_string(),
+            manifest: None,
         })
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum OperatingSystem {
     Apple,
     Linux,
@@ -108,7 +163,21 @@ impl FromStr for OperatingSystem {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+impl fmt::Display for OperatingSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use OperatingSystem::*;
+        let s = match self {
+            Apple => "apple",
+            Linux => "linux",
+            OpenBSD => "openbsd",
+            FreeBSD => "freebsd",
+            Windows => "windows",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum Arch {
     X86_32,
     AMD64,
@@ -156,14 +225,28 @@ impl FromStr for Arch {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Arch::*;
+        let s = match self {
+            X86_32 => "386",
+            AMD64 => "amd64",
+            Arm64 => "arm64",
+            Arm => "arm",
+            AppleUniversal => "universal",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Platform {
     pub os: OperatingSystem,
     pub arch: Arch,
 }
 
 impl Platform {
-    pub(crate) fn current() -> Self {
+    pub fn current() -> Self {
         match OperatingSystem::current() {
             x @ OperatingSystem::Apple => Self {
                 os: x,
@@ -177,6 +260,23 @@ impl Platform {
     }
 }
 
+impl Platform {
+    /// the name of the binary in the release archives
+    pub fn binary_name(&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Windows => "op.exe",
+            _ => "op",
+        }
+    }
+}
+
+/// the platform as in the binary filename, e.g. linux_amd64
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.os, self.arch)
+    }
+}
+
 impl FromStr for Platform {
     type Err = anyhow::Error;
 
@@ -249,6 +349,19 @@ mod test {
         assert!(Platform::from_str("linux_amd64").is_ok());
     }
 
+    #[test]
+    fn test_platform_round_trip() {
+        for s in [
+            "apple_universal",
+            "linux_386",
+            "freebsd_arm",
+            "windows_amd64",
+            "linux_arm64",
+        ] {
+            assert_eq!(s, Platform::from_str(s).unwrap().to_string());
+        }
+    }
+
     #[test]
     fn test_parse_release_expect_successful() {
         let u = "https://cache.agilebits.com/dist/1P/op/pkg/v1.12.1/op_freebsd_386_v1.12.1.zip";
@@ -264,6 +377,25 @@ mod test {
         assert_eq!(u, &rl.url);
     }
 
+    #[test]
+    fn test_parse_abbreviated_release_version() {
+        let u = "https://cache.agilebits.com/dist/1P/op/pkg/v0.6/op_linux_amd64_v0.6.zip";
+        let rl = Release::from_str(u).unwrap();
+        assert_eq!(Version::new(0, 6, 0), rl.version);
+        assert_eq!(Version::new(1, 12, 3), parse_version("1.12.3").unwrap());
+        assert!(parse_version("1.x").is_err());
+    }
+
+    #[test]
+    fn test_parse_beta_release_version() {
+        let u = "https://cache.agilebits.com/dist/1P/op2/pkg/v2.0.0-beta.8/op_linux_amd64_v2.0.0-beta.8.zip";
+        let rl = Release::from_str(u).unwrap();
+        assert_eq!(Version::parse("2.0.0-beta.8").unwrap(), rl.version);
+        let lv = LocalVersion::from_str("/tmp/op_linux_amd64_v2.0.0-beta.8").unwrap();
+        assert_eq!(rl.version, lv.version);
+        assert!(LocalVersion::from_str("/tmp/op_linux_amd64_v2.0.0-beta.8.zip").is_err());
+    }
+
     #[test]
     fn test_parse_release_expect_error() {
         let u = "https://cache.agilebits.com/dist/1P/op/pkg/v1.12.1/op_freebsd_586_v1.12.1.zip";
@@ -280,6 +412,7 @@ mod test {
             version,
             platform,
             path,
+            ..
         } = lv.unwrap();
         assert_eq!(Version::new(1, 12, 1), version);
         assert_eq!(
@@ -301,6 +434,7 @@ mod test {
             version,
             platform,
             path,
+            ..
         } = lv.unwrap();
         assert_eq!(Version::new(1, 12, 1), version);
         assert_eq!(
@@ -312,4 +446,18 @@ mod test {
         );
         assert_eq!(p, &path);
     }
+
+    #[test]
+    fn test_parse_windows_local_version() {
+        let p = r"C:\Users\u\.op_cli\op_windows_amd64_v2.1.0.exe";
+        let lv = LocalVersion::from_str(p).unwrap();
+        assert_eq!(Version::new(2, 1, 0), lv.version);
+        assert_eq!(Platform::from_str("windows_amd64").unwrap(), lv.platform);
+        assert_eq!("op.exe", lv.platform.binary_name());
+        assert_eq!(p, &lv.path);
+
+        // the archives and the signatures aren't binaries
+        assert!(LocalVersion::from_str("/x/op_windows_amd64_v2.1.0.zip").is_err());
+        assert!(LocalVersion::from_str("/x/op_linux_amd64_v2.1.0.sig").is_err());
+    }
 }
@@ -3,19 +3,57 @@ use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 use anyhow::anyhow;
+use io::{BufRead, Write};
+
+use crate::openv::cpio;
+use crate::openv::progress::Progress;
+use crate::openv::xar::XarArchive;
 
 pub enum UnpackOption {
     /// name the unpacked file after the zip archive entry
     UseEntryName(String),
-    /// name the unpacked file after the zip archive (without the extension)
+    /// name the unpacked file after the zip archive (without the extension), keeping the
+    /// extension of the entry, e.g. op_windows_amd64_v2.1.0.exe for op.exe
     UseArchiveName(String),
 }
 
+/// reports the bytes written through it
+struct ProgressWriter<'a, W: io::Write> {
+    inner: W,
+    written: u64,
+    total: Option<u64>,
+    progress: &'a Progress,
+}
+
+impl<'a, W: io::Write> ProgressWriter<'a, W> {
+    fn new(inner: W, total: Option<u64>, progress: &'a Progress) -> Self {
+        Self {
+            inner,
+            written: 0,
+            total,
+            progress,
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        self.progress.bytes(self.written, self.total);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(target_family = "unix")]
 fn handle_permission(p: &PathBuf, mut perms: Permissions) -> std::io::Result<()> {
     use std::os::unix::fs::PermissionsExt;
     perms.set_mode(0o700);
-    fs::set_permissions(&p, perms)
+    fs::set_permissions(p, perms)
 }
 
 #[cfg(target_family = "windows")]
@@ -27,91 +65,103 @@ pub fn unpack_one_to(
     zfilename: &Path,
     opt: UnpackOption,
     o_dir: &Path,
+    progress: &Progress,
 ) -> anyhow::Result<(u64, String)> {
-    let zipfile = std::fs::File::open(&zfilename)?;
+    let zipfile = std::fs::File::open(zfilename)?;
     let mut archive = zip::ZipArchive::new(zipfile)?;
     let (o_filename, mut file) = match opt {
         UnpackOption::UseEntryName(name) => (o_dir.join(&name), archive.by_name(&name)?),
         UnpackOption::UseArchiveName(name) => {
-            let basename = zfilename
+            let mut basename = zfilename
                 .file_stem()
                 .ok_or_else(|| anyhow!("irregular filename: {:?}", zfilename))?
-                .to_string_lossy()
-                .into_owned();
+                .to_os_string();
+            if let Some(ext) = Path::new(&name).extension() {
+                basename.push(".");
+                basename.push(ext);
+            }
             (o_dir.join(basename), archive.by_name(&name)?)
         }
     };
-    let mut o_file = fs::File::create(&o_filename)?;
+    let total = file.size();
+    let mut o_file = ProgressWriter::new(fs::File::create(&o_filename)?, Some(total), progress);
     let copied = io::copy(&mut file, &mut o_file)?;
     #[allow(unused_mut)]
     let mut perms = fs::metadata(&o_filename)?.permissions();
     handle_permission(&o_filename, perms)?;
-    return Ok((copied, o_filename.to_string_lossy().into_owned()));
+    Ok((copied, o_filename.to_string_lossy().into_owned()))
 }
 
-pub fn unpack_apple_pkg(pkg_filename: &Path, o_dir: &Path) -> anyhow::Result<String> {
-    let mut proc = std::process::Command::new("pkgutil")
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .arg("--expand")
-        .arg(&pkg_filename)
-        .arg(&o_dir)
-        .spawn()?;
-    proc.wait()?;
-    let payload = o_dir.join("op.pkg").join("Payload");
-
-    if fs::metadata(&payload)?.is_file() {
-        Ok(payload.to_string_lossy().into_owned())
-    } else {
-        Err(anyhow!(
-            "failed to extract op.pkg/Payload from apple installer file: {:?}",
-            &pkg_filename
-        ))
-    }
+/// extract the binary from a macOS package (.pkg) to `o_dir/o_name`: the xar container
+/// holds a gzip compressed cpio payload (op.pkg/Payload) that has the binary
+pub fn unpack_apple_pkg(
+    pkg_filename: &Path,
+    o_dir: &Path,
+    o_name: &str,
+    progress: &Progress,
+) -> anyhow::Result<(u64, String)> {
+    let mut xar = XarArchive::new(io::BufReader::new(fs::File::open(pkg_filename)?))?;
+    let payload = xar
+        .entries()
+        .iter()
+        .find(|e| e.path == "Payload" || e.path.ends_with(".pkg/Payload"))
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "failed to find the Payload in apple installer file: {:?}",
+                pkg_filename
+            )
+        })?;
+    let reader = xar.entry_reader(&payload)?;
+    unpack_cpio_payload(reader, "op", &o_dir.join(o_name), progress)
 }
 
+/// extract the binary from a (gzip compressed) cpio payload, e.g. the Payload of a .pkg;
+/// the binary is named `rename` if given, otherwise `o_name`
 pub fn unpack_apple_gzip(
     gz_filename: &Path,
     o_dir: &Path,
     o_name: &str,
     rename: Option<&str>,
+    progress: &Progress,
 ) -> anyhow::Result<(u64, String)> {
-    let input = io::BufReader::new(fs::File::open(gz_filename)?);
-    let mut decoder =
-        libflate::gzip::Decoder::new(input).expect("failed to read gzip (.pkg Payload) file!");
-    let cpio_filename = o_dir.to_path_buf().join("out.cpio");
-    let mut output = io::BufWriter::new(fs::File::create(&cpio_filename)?);
-    io::copy(&mut decoder, &mut output)?;
-    let mut proc = std::process::Command::new("cpio")
-        .current_dir(&o_dir)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .arg("-i")
-        .arg("-F")
-        .arg(&cpio_filename)
-        .spawn()?;
-    proc.wait()?;
-    let o_filename = o_dir.join(o_name);
-    if fs::metadata(&o_filename)?.is_file() {
-        if let Some(x) = rename {
-            let dest_filename = o_dir.join(x);
-            fs::copy(&o_filename, &dest_filename)?;
-            fs::remove_file(&o_filename)?; // move
-            fs::remove_file(&cpio_filename)?; // cpio temp
-            Ok((0, dest_filename.to_string_lossy().into_owned()))
-        } else {
-            fs::remove_file(&cpio_filename)?;
-            Ok((0, o_filename.to_string_lossy().into_owned()))
-        }
+    let o_filename = o_dir.join(rename.unwrap_or(o_name));
+    unpack_cpio_payload(fs::File::open(gz_filename)?, o_name, &o_filename, progress)
+}
+
+fn unpack_cpio_payload<'a, R: io::Read + 'a>(
+    reader: R,
+    entry_name: &str,
+    o_filename: &Path,
+    progress: &Progress,
+) -> anyhow::Result<(u64, String)> {
+    let mut reader = io::BufReader::new(reader);
+    // the payload may also be a plain cpio archive
+    let gzipped = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let payload: Box<dyn io::Read + 'a> = if gzipped {
+        Box::new(libflate::gzip::Decoder::new(reader)?)
     } else {
-        Err(anyhow!(
-            "failed to extract '{}' from cpio archive '{:?}'",
-            o_name,
-            &o_filename
-        ))
+        Box::new(reader)
+    };
+    let mut o_file = ProgressWriter::new(
+        io::BufWriter::new(fs::File::create(o_filename)?),
+        None,
+        progress,
+    );
+    let copied = cpio::extract_entry(payload, entry_name, &mut o_file).and_then(|n| {
+        o_file.flush()?;
+        Ok(n)
+    });
+    drop(o_file);
+    if copied.is_err() {
+        fs::remove_file(o_filename)?;
     }
+    let copied = copied?;
+    let o_filename = o_filename.to_path_buf();
+    #[allow(unused_mut)]
+    let mut perms = fs::metadata(&o_filename)?.permissions();
+    handle_permission(&o_filename, perms)?;
+    Ok((copied, o_filename.to_string_lossy().into_owned()))
 }
 
 #[cfg(test)]
@@ -133,6 +183,7 @@ mod test {
             &zfilename,
             UnpackOption::UseEntryName("op".to_string()),
             &tmp,
+            &Progress::default(),
         );
         assert!(res.is_ok());
         assert!(res.unwrap().1.ends_with("op"));
@@ -151,11 +202,44 @@ mod test {
             &zfilename,
             UnpackOption::UseArchiveName("op".to_string()),
             &tmp,
+            &Progress::default(),
         );
         assert!(res.is_ok());
         assert!(res.unwrap().1.ends_with("op_linux_amd64_v1.11.2"));
     }
 
+    #[test]
+    fn test_unpack_windows_zip() {
+        let zfilename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("archives")
+            .join("op_windows_amd64_v2.1.0.zip");
+        let o_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("unpack_windows_zip");
+        let _dont_care = fs::remove_dir_all(&o_dir);
+        fs::create_dir_all(&o_dir).unwrap();
+        let (_, o_filename) = unpack_one_to(
+            &zfilename,
+            UnpackOption::UseArchiveName("op.exe".to_string()),
+            &o_dir,
+            &Progress::default(),
+        )
+        .unwrap();
+        assert!(o_filename.ends_with("op_windows_amd64_v2.1.0.exe"));
+        assert!(fs::metadata(&o_filename).unwrap().is_file());
+        // there's no unix binary in it
+        let res = unpack_one_to(
+            &zfilename,
+            UnpackOption::UseArchiveName("op".to_string()),
+            &o_dir,
+            &Progress::default(),
+        );
+        assert!(res.is_err());
+        fs::remove_dir_all(&o_dir).unwrap();
+    }
+
     #[test]
     fn test_unpack_apple_gzip_file() {
         let gzip_filename: PathBuf = [
@@ -171,9 +255,66 @@ mod test {
             "/tmp".as_ref(),
             "op",
             Some("op_there_is_a_cow_1.1.2"),
+            &Progress::default(),
         )
         .unwrap();
 
         assert!(fs::metadata(o_filename).unwrap().is_file());
     }
+
+    #[test]
+    fn test_unpack_apple_pkg() {
+        let pkg_filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("archives")
+            .join("op_apple_universal_v2.1.0.pkg");
+        let o_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("unpack_apple_pkg");
+        let _dont_care = fs::remove_dir_all(&o_dir);
+        fs::create_dir_all(&o_dir).unwrap();
+        let (n, o_filename) = unpack_apple_pkg(
+            &pkg_filename,
+            &o_dir,
+            "op_apple_universal_v2.1.0",
+            &Progress::default(),
+        )
+        .unwrap();
+        assert_eq!(21, n);
+        assert!(o_filename.ends_with("op_apple_universal_v2.1.0"));
+        assert_eq!(
+            "#!/bin/sh\necho 2.1.0\n",
+            fs::read_to_string(&o_filename).unwrap()
+        );
+        // nothing else is left behind
+        assert_eq!(1, fs::read_dir(&o_dir).unwrap().count());
+
+        // not a package
+        let zfilename = pkg_filename.with_file_name("op_linux_amd64_v1.11.2.zip");
+        assert!(unpack_apple_pkg(&zfilename, &o_dir, "op", &Progress::default()).is_err());
+        fs::remove_dir_all(&o_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_plain_cpio_payload() {
+        let cpio_filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("archives")
+            .join("payload_newc.cpio");
+        let o_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("unpack_plain_cpio_payload");
+        let _dont_care = fs::remove_dir_all(&o_dir);
+        fs::create_dir_all(&o_dir).unwrap();
+        let (n, _) =
+            unpack_apple_gzip(&cpio_filename, &o_dir, "op", None, &Progress::default()).unwrap();
+        assert_eq!(21, n);
+        assert!(
+            unpack_apple_gzip(&cpio_filename, &o_dir, "nope", None, &Progress::default()).is_err()
+        );
+        assert!(!o_dir.join("nope").exists());
+        fs::remove_dir_all(&o_dir).unwrap();
+    }
 }
@@ -0,0 +1,366 @@
+// to verify the downloaded archives and the unpacked binaries:
+// - the sha-256 digest of the archive against a caller-supplied (pinned) digest
+// - the detached signature (op.sig) shipped inside the zip archive against the
+//   1Password CLI signing key, using the host's gpg; the public key is bundled (see
+//   keys/) and imported into a temporary keyring for every verification, so neither the
+//   host's keyring nor a key already trusted there is involved; the macOS packages carry
+//   no op.sig (they are signed for the Apple installer) and are not checked this way
+// - the sha-256 digest of an installed binary against its install manifest
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::openv::types::LocalVersion;
+
+/// the fingerprint of the key that 1Password signs the CLI binaries with
+pub const ONE_PASSWORD_SIGNING_KEY: &str = "3FEF9748469ADBE15DA7CA80AC2D62742012EA22";
+
+/// the armored public key of ONE_PASSWORD_SIGNING_KEY
+pub const ONE_PASSWORD_PUBLIC_KEY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/keys/1password-cli.asc"
+));
+
+#[derive(Debug, PartialEq, Error)]
+pub enum VerificationError {
+    #[error("checksum mismatch: {filename}, expect: {expected}, actual: {actual}")]
+    ChecksumMismatch {
+        filename: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("missing signature file: {0}")]
+    MissingSignature(String),
+
+    #[error("bad signature: {0}")]
+    BadSignature(String),
+
+    #[error("no install manifest for: {0}")]
+    MissingManifest(String),
+
+    #[error("bad public key: {0}")]
+    BadPublicKey(String),
+}
+
+/// how to verify the signature of the unpacked binary
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureCheck {
+    /// the fingerprint of the key that must have made the signature
+    pub fingerprint: String,
+    /// the armored public key, imported into a temporary gpg home for the verification
+    pub public_key: Option<String>,
+    /// the gpg home directory that holds the public key if none is given; None uses the
+    /// default one
+    pub gnupg_home: Option<PathBuf>,
+}
+
+impl Default for SignatureCheck {
+    fn default() -> Self {
+        Self {
+            fingerprint: ONE_PASSWORD_SIGNING_KEY.to_string(),
+            public_key: Some(ONE_PASSWORD_PUBLIC_KEY.to_string()),
+            gnupg_home: None,
+        }
+    }
+}
+
+/// a gpg home of its own, removed on drop
+struct TempGnupgHome(PathBuf);
+
+impl TempGnupgHome {
+    fn new() -> std::io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dirname = std::env::temp_dir().join(format!(
+            "openv-gnupg-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        std::fs::create_dir_all(&dirname)?;
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dirname, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self(dirname))
+    }
+}
+
+impl Drop for TempGnupgHome {
+    fn drop(&mut self) {
+        let _dont_care = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn gpg(gnupg_home: Option<&Path>) -> Command {
+    let mut cmd = Command::new("gpg");
+    if let Some(home) = gnupg_home {
+        cmd.env("GNUPGHOME", home);
+    }
+    cmd.stdin(Stdio::null()).arg("--batch");
+    cmd
+}
+
+/// import the armored key into the gpg home; it must be the key of the fingerprint
+fn import_public_key(gnupg_home: &Path, armored: &str, fingerprint: &str) -> anyhow::Result<()> {
+    use VerificationError::BadPublicKey;
+    if !armored.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+        return Err(BadPublicKey(format!("no armored key for {}", fingerprint)).into());
+    }
+    let key_filename = gnupg_home.join("key.asc");
+    std::fs::write(&key_filename, armored)?;
+    let imported = gpg(Some(gnupg_home))
+        .arg("--import")
+        .arg(&key_filename)
+        .output()?;
+    if !imported.status.success() {
+        return Err(
+            BadPublicKey(String::from_utf8_lossy(&imported.stderr).trim().to_string()).into(),
+        );
+    }
+    let listing = gpg(Some(gnupg_home))
+        .arg("--with-colons")
+        .arg("--list-keys")
+        .output()?;
+    let found = String::from_utf8_lossy(&listing.stdout)
+        .lines()
+        .filter(|line| line.starts_with("fpr:"))
+        .filter_map(|line| line.split(':').nth(9))
+        .any(|fpr| fpr.eq_ignore_ascii_case(fingerprint));
+    if found {
+        Ok(())
+    } else {
+        Err(BadPublicKey(format!("the key isn't {}", fingerprint)).into())
+    }
+}
+
+pub fn sha256_file(filename: &Path) -> anyhow::Result<String> {
+    let mut file = std::fs::File::open(filename)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn verify_sha256(filename: &Path, expected: &str) -> anyhow::Result<()> {
+    let actual = sha256_file(filename)?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(VerificationError::ChecksumMismatch {
+            filename: filename.to_string_lossy().into_owned(),
+            expected: expected.trim().to_lowercase(),
+            actual,
+        }))
+    }
+}
+
+/// re-hash the installed binary and compare it against the install manifest
+pub fn verify_installation(local_version: &LocalVersion) -> anyhow::Result<()> {
+    let manifest = local_version
+        .manifest
+        .as_ref()
+        .ok_or_else(|| VerificationError::MissingManifest(local_version.path.clone()))?;
+    verify_sha256(Path::new(&local_version.path), &manifest.sha256)
+}
+
+/// verify the detached signature with gpg; the signature must be valid and made by
+/// the key of the expected fingerprint
+pub fn verify_signature(
+    binary_filename: &Path,
+    sig_filename: &Path,
+    check: &SignatureCheck,
+) -> anyhow::Result<()> {
+    use VerificationError::*;
+    if !sig_filename.is_file() {
+        return Err(anyhow::Error::new(MissingSignature(
+            sig_filename.to_string_lossy().into_owned(),
+        )));
+    }
+    let temp_home = match &check.public_key {
+        Some(armored) => {
+            let home = TempGnupgHome::new()?;
+            import_public_key(&home.0, armored, &check.fingerprint)?;
+            Some(home)
+        }
+        None => None,
+    };
+    let gnupg_home = temp_home
+        .as_ref()
+        .map(|home| home.0.as_path())
+        .or(check.gnupg_home.as_deref());
+    let out = gpg(gnupg_home)
+        .arg("--status-fd")
+        .arg("1")
+        .arg("--verify")
+        .arg(sig_filename)
+        .arg(binary_filename)
+        .output()?;
+    let status = String::from_utf8_lossy(&out.stdout);
+    let signed_by_key = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .flat_map(|line| line.split_whitespace())
+        .any(|fpr| fpr.eq_ignore_ascii_case(&check.fingerprint));
+    if out.status.success() && signed_by_key {
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(BadSignature(
+            binary_filename.to_string_lossy().into_owned(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_verify_sha256_expect_successful() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("archives")
+            .join("op_linux_amd64_v1.11.2.zip");
+        let digest = "7a2bc2f3b81897f7ee9592186e89b1694ef81bd604f8b6fcdd49fc88a737e177";
+        assert_eq!(digest, sha256_file(&filename).unwrap());
+        assert!(verify_sha256(&filename, &digest.to_uppercase()).is_ok());
+    }
+
+    #[test]
+    fn test_verify_sha256_expect_mismatch() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("archives")
+            .join("op_linux_amd64_v1.11.2.zip");
+        let result = verify_sha256(&filename, "deadbeef");
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<VerificationError>(),
+            Some(VerificationError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_signature_missing_sig_file() {
+        let filename = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("fake_binaries")
+            .join("op_linux_amd64_v1.11.2");
+        let result = verify_signature(
+            &filename,
+            &filename.with_extension("sig"),
+            &SignatureCheck::default(),
+        );
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<VerificationError>(),
+            Some(VerificationError::MissingSignature(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[ignore = "keys/1password-cli.asc is empty until the release key is exported into it"]
+    fn test_import_bundled_public_key() {
+        let home = TempGnupgHome::new().unwrap();
+        assert!(
+            import_public_key(&home.0, ONE_PASSWORD_PUBLIC_KEY, ONE_PASSWORD_SIGNING_KEY).is_ok()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_verify_signature_with_generated_key() {
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("verify_signature");
+        let gnupg_home = dirname.join("gnupg");
+        let _dont_care = fs::remove_dir_all(&dirname);
+        assert!(fs::create_dir_all(&gnupg_home).is_ok());
+        let gpg = |args: &[&str]| {
+            Command::new("gpg")
+                .env("GNUPGHOME", &gnupg_home)
+                .args(["--batch", "--passphrase", "", "--pinentry-mode", "loopback"])
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        let uid = "openv test <openv@example.com>";
+        assert!(gpg(&["--quick-gen-key", uid, "ed25519", "sign", "never"])
+            .status
+            .success());
+        let listing = gpg(&["--with-colons", "--list-keys", uid]);
+        let fingerprint = String::from_utf8_lossy(&listing.stdout)
+            .lines()
+            .find(|line| line.starts_with("fpr:"))
+            .and_then(|line| line.split(':').nth(9))
+            .unwrap()
+            .to_string();
+
+        let binary = dirname.join("op");
+        let sig = dirname.join("op.sig");
+        fs::write(&binary, b"there is a cow").unwrap();
+        let binary_str = binary.to_string_lossy().into_owned();
+        let sig_str = sig.to_string_lossy().into_owned();
+        assert!(gpg(&["--output", &sig_str, "--detach-sign", &binary_str])
+            .status
+            .success());
+
+        let check = SignatureCheck {
+            fingerprint: fingerprint.clone(),
+            public_key: None,
+            gnupg_home: Some(gnupg_home.clone()),
+        };
+        assert!(verify_signature(&binary, &sig, &check).is_ok());
+
+        // signed by another key
+        let other = SignatureCheck {
+            fingerprint: ONE_PASSWORD_SIGNING_KEY.to_string(),
+            public_key: None,
+            gnupg_home: Some(gnupg_home.clone()),
+        };
+        assert!(verify_signature(&binary, &sig, &other).is_err());
+
+        // the armored key alone, imported into a fresh keyring
+        let armored = gpg(&["--armor", "--export", &fingerprint]);
+        let bundled = SignatureCheck {
+            fingerprint: fingerprint.clone(),
+            public_key: Some(String::from_utf8(armored.stdout).unwrap()),
+            gnupg_home: None,
+        };
+        assert!(verify_signature(&binary, &sig, &bundled).is_ok());
+        let not_the_key = SignatureCheck {
+            fingerprint: ONE_PASSWORD_SIGNING_KEY.to_string(),
+            ..bundled.clone()
+        };
+        let result = verify_signature(&binary, &sig, &not_the_key);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<VerificationError>(),
+            Some(VerificationError::BadPublicKey(_))
+        ));
+
+        // tampered binary
+        fs::write(&binary, b"there is a moose").unwrap();
+        for check in [&check, &bundled] {
+            let result = verify_signature(&binary, &sig, check);
+            assert!(matches!(
+                result.unwrap_err().downcast_ref::<VerificationError>(),
+                Some(VerificationError::BadSignature(_))
+            ));
+        }
+
+        let _dont_care = fs::remove_dir_all(&dirname);
+    }
+}
@@ -0,0 +1,230 @@
+// to select a local version per project, similar to pyenv and goenv; the precedence is:
+// - the OPENV_VERSION env var;
+// - the nearest .op-version file, walking up from the current directory;
+// - the global version file in the openv home directory;
+// - the newest installed version.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use semver::VersionReq;
+use thiserror::Error;
+use tokio::fs;
+
+use crate::openv::local_versions::{find_local_version, find_matching_local_version};
+use crate::openv::types::LocalVersion;
+
+pub const VERSION_ENV: &str = "OPENV_VERSION";
+pub const LOCAL_VERSION_FILENAME: &str = ".op-version";
+/// the name of the global version file in the openv home directory
+pub const GLOBAL_VERSION_FILENAME: &str = "version";
+
+#[derive(Debug, PartialEq, Error)]
+pub enum VersionFileError {
+    #[error("{0} is empty.")]
+    Empty(String),
+
+    #[error("version {req} set by {set_by} is not installed.")]
+    NotInstalled { req: String, set_by: VersionSource },
+}
+
+/// where the selected version comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionSource {
+    Env,
+    LocalFile(PathBuf),
+    GlobalFile(PathBuf),
+    LatestInstalled,
+}
+
+impl fmt::Display for VersionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSource::Env => write!(f, "{}", VERSION_ENV),
+            VersionSource::LocalFile(p) | VersionSource::GlobalFile(p) => {
+                write!(f, "{}", p.display())
+            }
+            VersionSource::LatestInstalled => write!(f, "the latest installed version"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResolvedVersion {
+    pub local_version: LocalVersion,
+    pub source: VersionSource,
+}
+
+/// a bare (possibly partial) version is an exact pin, e.g. 1.12 selects 1.12.x;
+/// anything else is a version requirement, e.g. ~1.11 or >=2
+pub fn parse_version_pin(s: &str) -> anyhow::Result<VersionReq> {
+    let s = s.trim();
+    let s = s.strip_prefix('v').unwrap_or(s);
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        Ok(VersionReq::parse(&format!("={}", s))?)
+    } else {
+        Ok(VersionReq::parse(s)?)
+    }
+}
+
+/// the first non-empty line of the version file
+async fn read_version_file(filename: &Path) -> anyhow::Result<VersionReq> {
+    let text = fs::read_to_string(filename).await?;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .ok_or_else(|| VersionFileError::Empty(filename.display().to_string()))?;
+    parse_version_pin(line)
+}
+
+pub async fn write_version_file(filename: &Path, req: &str) -> anyhow::Result<()> {
+    parse_version_pin(req)?;
+    fs::write(filename, format!("{}\n", req.trim())).await?;
+    Ok(())
+}
+
+/// the nearest .op-version file in the directory or its ancestors
+pub fn find_local_version_file(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .map(|d| d.join(LOCAL_VERSION_FILENAME))
+        .find(|p| p.is_file())
+}
+
+pub fn global_version_file(dirname: &Path) -> PathBuf {
+    dirname.join(GLOBAL_VERSION_FILENAME)
+}
+
+/// the version requirement and its source, if any is set
+pub async fn selected_version(
+    dirname: &Path,
+    cwd: &Path,
+) -> anyhow::Result<Option<(VersionReq, VersionSource)>> {
+    selected_version_with(dirname, cwd, std::env::var(VERSION_ENV).ok()).await
+}
+
+pub(crate) async fn selected_version_with(
+    dirname: &Path,
+    cwd: &Path,
+    env: Option<String>,
+) -> anyhow::Result<Option<(VersionReq, VersionSource)>> {
+    if let Some(v) = env.filter(|v| !v.trim().is_empty()) {
+        return Ok(Some((parse_version_pin(&v)?, VersionSource::Env)));
+    }
+    if let Some(p) = find_local_version_file(cwd) {
+        return Ok(Some((
+            read_version_file(&p).await?,
+            VersionSource::LocalFile(p),
+        )));
+    }
+    let p = global_version_file(dirname);
+    if p.is_file() {
+        return Ok(Some((
+            read_version_file(&p).await?,
+            VersionSource::GlobalFile(p),
+        )));
+    }
+    Ok(None)
+}
+
+/// the local version to use in the directory `cwd`
+pub async fn resolve_version(dirname: &Path, cwd: &Path) -> anyhow::Result<ResolvedVersion> {
+    resolve_version_with(dirname, cwd, std::env::var(VERSION_ENV).ok()).await
+}
+
+async fn resolve_version_with(
+    dirname: &Path,
+    cwd: &Path,
+    env: Option<String>,
+) -> anyhow::Result<ResolvedVersion> {
+    match selected_version_with(dirname, cwd, env).await? {
+        Some((req, source)) => {
+            let local_version = find_matching_local_version(dirname, &req)
+                .await
+                .map_err(|_| VersionFileError::NotInstalled {
+                    req: req.to_string(),
+                    set_by: source.clone(),
+                })?;
+            Ok(ResolvedVersion {
+                local_version,
+                source,
+            })
+        }
+        None => Ok(ResolvedVersion {
+            local_version: find_local_version(dirname).await?,
+            source: VersionSource::LatestInstalled,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use semver::Version;
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_version_pin() {
+        let req = parse_version_pin("1.12").unwrap();
+        assert!(req.matches(&Version::new(1, 12, 3)));
+        assert!(!req.matches(&Version::new(1, 13, 0)));
+        let req = parse_version_pin("v1.12.1\n").unwrap();
+        assert!(!req.matches(&Version::new(1, 12, 3)));
+        let req = parse_version_pin("~1.11").unwrap();
+        assert!(req.matches(&Version::new(1, 11, 4)));
+        assert!(parse_version_pin("latest").is_err());
+        assert!(parse_version_pin("").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_resolve_version_precedence() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("resolve_version_precedence");
+        let _dont_care = std::fs::remove_dir_all(&root);
+        let home = root.join("home");
+        let cwd = root.join("project").join("src").join("nested");
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&cwd).unwrap();
+        for v in ["1.9.0", "1.10.3", "1.11.2", "1.12.1"] {
+            std::fs::File::create(home.join(format!("op_linux_amd64_v{}", v))).unwrap();
+        }
+        let rt = Runtime::new().unwrap();
+        let resolve = |env: Option<&str>| {
+            rt.block_on(resolve_version_with(&home, &cwd, env.map(str::to_string)))
+        };
+
+        let rv = resolve(None).unwrap();
+        assert_eq!(VersionSource::LatestInstalled, rv.source);
+        assert_eq!(Version::new(1, 12, 1), rv.local_version.version);
+
+        let global = global_version_file(&home);
+        rt.block_on(write_version_file(&global, "1.9")).unwrap();
+        let rv = resolve(None).unwrap();
+        assert_eq!(VersionSource::GlobalFile(global), rv.source);
+        assert_eq!(Version::new(1, 9, 0), rv.local_version.version);
+
+        let local = root.join("project").join(LOCAL_VERSION_FILENAME);
+        rt.block_on(write_version_file(&local, "~1.10")).unwrap();
+        let rv = resolve(None).unwrap();
+        assert_eq!(VersionSource::LocalFile(local), rv.source);
+        assert_eq!(Version::new(1, 10, 3), rv.local_version.version);
+
+        let rv = resolve(Some("1.11.2")).unwrap();
+        assert_eq!(VersionSource::Env, rv.source);
+        assert_eq!(Version::new(1, 11, 2), rv.local_version.version);
+
+        let err = resolve(Some("2")).unwrap_err();
+        assert_eq!(
+            Some(&VersionFileError::NotInstalled {
+                req: "=2".to_string(),
+                set_by: VersionSource::Env
+            }),
+            err.downcast_ref::<VersionFileError>()
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
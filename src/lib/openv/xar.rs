@@ -0,0 +1,237 @@
+// to read the xar container of a macOS flat package (.pkg):
+// - a big-endian header: magic (xar!), header size, version, the compressed and the
+//   uncompressed sizes of the toc, the checksum algorithm;
+// - the table of contents (toc): zlib compressed xml that describes the files;
+// - the heap, holding the (possibly zlib compressed) data of the files.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use thiserror::Error;
+
+const MAGIC: u32 = 0x7861_7221;
+const HEADER_SIZE: usize = 28;
+/// the toc of a package is a few kilobytes; the header is not trusted beyond this
+const MAX_TOC_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum XarError {
+    #[error("not a xar archive (bad magic).")]
+    BadMagic,
+
+    #[error("malformed xar table of contents: {0}")]
+    MalformedToc(String),
+
+    #[error("unsupported xar encoding: {0}")]
+    UnsupportedEncoding(String),
+
+    #[error("xar table of contents too large: {0} bytes")]
+    TocTooLarge(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// application/octet-stream
+    Stored,
+    /// application/x-gzip, which is a zlib stream despite the name
+    Zlib,
+}
+
+/// a file (with data) in the archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct XarEntry {
+    /// relative to the root of the archive, e.g. op.pkg/Payload
+    pub path: String,
+    /// relative to the start of the heap
+    pub offset: u64,
+    /// the size in the heap
+    pub length: u64,
+    /// the size once decoded
+    pub size: u64,
+    pub encoding: Encoding,
+}
+
+pub struct XarArchive<R: Read + Seek> {
+    inner: R,
+    heap_start: u64,
+    entries: Vec<XarEntry>,
+}
+
+impl<R: Read + Seek> XarArchive<R> {
+    pub fn new(mut inner: R) -> anyhow::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        inner.read_exact(&mut header)?;
+        let be_u16 = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+        let be_u64 = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&header[i..i + 8]);
+            u64::from_be_bytes(b)
+        };
+        if u32::from_be_bytes([header[0], header[1], header[2], header[3]]) != MAGIC {
+            return Err(XarError::BadMagic.into());
+        }
+        let header_size = be_u16(4) as u64;
+        let toc_compressed = be_u64(8);
+        let toc_uncompressed = be_u64(16);
+        if toc_compressed > MAX_TOC_SIZE || toc_uncompressed > MAX_TOC_SIZE {
+            return Err(XarError::TocTooLarge(toc_compressed.max(toc_uncompressed)).into());
+        }
+
+        inner.seek(SeekFrom::Start(header_size))?;
+        let mut toc = String::with_capacity(toc_uncompressed as usize);
+        // no more than the header promises
+        libflate::zlib::Decoder::new((&mut inner).take(toc_compressed))?
+            .take(toc_uncompressed)
+            .read_to_string(&mut toc)?;
+        Ok(Self {
+            inner,
+            heap_start: header_size + toc_compressed,
+            entries: parse_toc(&toc)?,
+        })
+    }
+
+    pub fn entries(&self) -> &[XarEntry] {
+        &self.entries
+    }
+
+    /// the decoded data of the entry
+    pub fn entry_reader(&mut self, entry: &XarEntry) -> anyhow::Result<Box<dyn Read + '_>> {
+        self.inner
+            .seek(SeekFrom::Start(self.heap_start + entry.offset))?;
+        let raw = (&mut self.inner).take(entry.length);
+        Ok(match entry.encoding {
+            Encoding::Stored => Box::new(raw),
+            Encoding::Zlib => Box::new(libflate::zlib::Decoder::new(raw)?),
+        })
+    }
+}
+
+fn child<'a, 'i>(node: roxmltree::Node<'a, 'i>, name: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child_u64(node: roxmltree::Node, name: &str) -> anyhow::Result<u64> {
+    let text = child(node, name)
+        .and_then(|n| n.text())
+        .ok_or_else(|| XarError::MalformedToc(format!("missing <{}>", name)))?;
+    Ok(text
+        .trim()
+        .parse()
+        .map_err(|_| XarError::MalformedToc(format!("<{}>{}</{}>", name, text, name)))?)
+}
+
+/// the files with data, walking the nested <file> elements
+fn parse_toc(xml: &str) -> anyhow::Result<Vec<XarEntry>> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| XarError::MalformedToc(e.to_string()))?;
+    let toc = child(doc.root_element(), "toc")
+        .ok_or_else(|| XarError::MalformedToc("missing <toc>".to_string()))?;
+    let mut entries = Vec::new();
+    walk_files(toc, "", &mut entries)?;
+    Ok(entries)
+}
+
+fn walk_files(
+    parent: roxmltree::Node,
+    dir: &str,
+    entries: &mut Vec<XarEntry>,
+) -> anyhow::Result<()> {
+    for file in parent.children().filter(|n| n.has_tag_name("file")) {
+        let name = child(file, "name")
+            .and_then(|n| n.text())
+            .ok_or_else(|| XarError::MalformedToc("missing <name>".to_string()))?;
+        let path = if dir.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        if let Some(data) = child(file, "data") {
+            let style = child(data, "encoding")
+                .and_then(|n| n.attribute("style"))
+                .unwrap_or("application/octet-stream");
+            let encoding = match style {
+                "application/octet-stream" => Encoding::Stored,
+                "application/x-gzip" => Encoding::Zlib,
+                other => return Err(XarError::UnsupportedEncoding(other.to_string()).into()),
+            };
+            entries.push(XarEntry {
+                path: path.clone(),
+                offset: child_u64(data, "offset")?,
+                length: child_u64(data, "length")?,
+                size: child_u64(data, "size")?,
+                encoding,
+            });
+        }
+        walk_files(file, &path, entries)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture() -> File {
+        File::open(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("archives")
+                .join("op_apple_universal_v2.1.0.pkg"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_list_entries() {
+        let xar = XarArchive::new(fixture()).unwrap();
+        let paths: Vec<_> = xar.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(vec!["Distribution", "op.pkg/Payload"], paths);
+        assert_eq!(Encoding::Zlib, xar.entries()[0].encoding);
+        assert_eq!(Encoding::Stored, xar.entries()[1].encoding);
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let mut xar = XarArchive::new(fixture()).unwrap();
+        let entries = xar.entries().to_vec();
+        for entry in &entries {
+            let mut data = Vec::new();
+            xar.entry_reader(entry)
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            assert_eq!(entry.size, data.len() as u64);
+        }
+        let mut distribution = String::new();
+        xar.entry_reader(&entries[0])
+            .unwrap()
+            .read_to_string(&mut distribution)
+            .unwrap();
+        assert!(distribution.contains("<title>1Password CLI</title>"));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let rs = XarArchive::new(Cursor::new(vec![0u8; 64]));
+        assert_eq!(
+            Some(&XarError::BadMagic),
+            rs.err().unwrap().downcast_ref::<XarError>()
+        );
+    }
+
+    #[test]
+    fn test_toc_too_large() {
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(&MAGIC.to_be_bytes());
+        header[4..6].copy_from_slice(&(HEADER_SIZE as u16).to_be_bytes());
+        header[8..16].copy_from_slice(&64u64.to_be_bytes());
+        header[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+        let rs = XarArchive::new(Cursor::new(header));
+        assert_eq!(
+            Some(&XarError::TocTooLarge(u64::MAX)),
+            rs.err().unwrap().downcast_ref::<XarError>()
+        );
+    }
+}
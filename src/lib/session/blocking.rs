@@ -0,0 +1,101 @@
+// the blocking counterparts of the session functions, for the simple tools that don't
+// run a tokio runtime; enabled by the `blocking` feature.
+
+use std::io::Write;
+
+use crate::session::error::{OpAction, OpError};
+use crate::session::item::Item;
+use crate::session::signin::{local_accounts_command, parse_session, sign_in_command};
+use crate::session::types::*;
+use crate::ReleaseNoteUrl;
+
+fn local_accounts(conf: &SessionConfig, major: ReleaseNoteUrl) -> Result<Vec<Account>, OpError> {
+    let out = local_accounts_command(conf, &major)
+        .output()
+        .map_err(|e| OpError::from_spawn(&conf.bin_filename, e))?;
+    OpError::check(&out, OpAction::Other)?;
+    Ok(Account::from_descriptions(&String::from_utf8_lossy(
+        &out.stdout,
+    )))
+}
+
+/// list all the accounts configured in the host system; only work with 1password cli 1.x
+pub fn local_accounts_v1(conf: &SessionConfig) -> Result<Vec<Account>, OpError> {
+    local_accounts(conf, ReleaseNoteUrl::V1)
+}
+
+/// list all the accounts configured in the host system; only work with 1password cli 2.x
+pub fn local_accounts_v2(conf: &SessionConfig) -> Result<Vec<Account>, OpError> {
+    local_accounts(conf, ReleaseNoteUrl::V2)
+}
+
+fn sign_in_shorthand(conf: &SessionConfig, major: ReleaseNoteUrl) -> Result<Session, OpError> {
+    let password = conf
+        .password
+        .password(&conf.shorthand)
+        .map_err(|e| OpError::NoPassword(e.to_string()))?;
+    let mut proc = sign_in_command(conf, &major)
+        .spawn()
+        .map_err(|e| OpError::from_spawn(&conf.bin_filename, e))?;
+    let mut stdin = proc
+        .stdin
+        .take()
+        .ok_or_else(|| OpError::Io("signin: fail to take stdin".to_string()))?;
+    write!(stdin, "{}", password).map_err(OpError::from_io)?;
+    drop(stdin);
+    let out = proc.wait_with_output().map_err(OpError::from_io)?;
+    OpError::check(&out, OpAction::SignIn)?;
+    parse_session(conf, major, &String::from_utf8_lossy(&out.stdout))
+}
+
+/// this signin function works with 1password cli 1.x
+pub fn sign_in_shorthand_v1(conf: &SessionConfig) -> Result<Session, OpError> {
+    sign_in_shorthand(conf, ReleaseNoteUrl::V1)
+}
+
+/// this signin function works with 1password cli 2.x
+pub fn sign_in_shorthand_v2(conf: &SessionConfig) -> Result<Session, OpError> {
+    sign_in_shorthand(conf, ReleaseNoteUrl::V2)
+}
+
+/// the item by name, id or link
+pub fn get_item(session: &Session, item: &str) -> Result<Item, OpError> {
+    let out = session
+        .get_item_command(item)
+        .output()
+        .map_err(|e| OpError::from_spawn(&session.bin_filename, e))?;
+    session.parse_item(out)
+}
+
+/// the values of the fields (by label or id) of the item, in the order given
+pub fn item_fields(session: &Session, item: &str, fields: &[&str]) -> Result<Vec<String>, OpError> {
+    get_item(session, item)?.field_values(fields)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::signin::test::fake_op;
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_item_fields_blocking() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/items/item_v1.json");
+        let op = fake_op(
+            "session_item_fields_blocking",
+            &format!(
+                "[ \"$1 $2 $3\" = 'get item doom' ] || exit 1\ncat {}",
+                fixture
+            ),
+        );
+        let session = Session {
+            bin_filename: op.to_string_lossy().into_owned(),
+            shorthand: "my".to_string(),
+            session_code: SessionCode::V1PlainString("abc".to_string()),
+            major_version: ReleaseNoteUrl::V1,
+        };
+        let values = item_fields(&session, "doom", &["username", "password"]).unwrap();
+        assert_eq!(vec!["doomguy", "iddqd,idkfa"], values);
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
+}
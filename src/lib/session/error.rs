@@ -0,0 +1,193 @@
+// to classify the failures of op; both majors print a line on stderr and exit with 1:
+//   [ERROR] 2021/11/20 10:14:04 You are not currently signed in. Please run `op signin --help` for instructions
+//   [ERROR] 2021/11/20 10:14:04 "doom" doesn't seem to be an item. Specify the item ... (1.x)
+//   [ERROR] 2022/01/08 02:51:50 "doom" isn't an item. Specify the item ... (2.x)
+// a 401 means the master password is wrong while signing in, and the session is expired
+// otherwise.
+
+use std::process::Output;
+
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum OpError {
+    #[error("not signed in.")]
+    NotSignedIn,
+
+    #[error("the session is expired; sign in again.")]
+    SessionExpired,
+
+    #[error("no item {0}.")]
+    ItemNotFound(String),
+
+    #[error("more than one item matches {0}; use its id.")]
+    AmbiguousItem(String),
+
+    #[error("no vault {0}.")]
+    VaultNotFound(String),
+
+    #[error("wrong master password.")]
+    WrongPassword,
+
+    #[error("op binary not found: {0}")]
+    BinaryNotFound(String),
+
+    #[error("unexpected output from op: {0}")]
+    UnexpectedOutput(String),
+
+    #[error("no field {field} in item {item}.")]
+    FieldNotFound { item: String, field: String },
+
+    #[error("can't get the master password: {0}")]
+    NoPassword(String),
+
+    #[error("can't run op: {0}")]
+    Io(String),
+}
+
+/// what op was asked to do; it decides what a 401 means
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OpAction {
+    SignIn,
+    Other,
+}
+
+/// the message without the [ERROR] <date> <time> prefix
+fn message(stderr: &str) -> &str {
+    let line = stderr.trim();
+    match line.strip_prefix("[ERROR]") {
+        Some(rest) => rest
+            .trim_start()
+            .splitn(3, ' ')
+            .nth(2)
+            .unwrap_or(rest)
+            .trim(),
+        None => line,
+    }
+}
+
+/// the first "quoted" name in the message, e.g. the item
+fn quoted(msg: &str) -> Option<&str> {
+    let (_, rest) = msg.split_once('"')?;
+    Some(rest.split_once('"')?.0)
+}
+
+impl OpError {
+    pub(crate) fn classify(code: Option<i32>, stderr: &str, action: OpAction) -> Self {
+        let msg = message(stderr);
+        let lower = msg.to_lowercase();
+        let name = || quoted(msg).unwrap_or(msg).to_string();
+        let has = |xs: &[&str]| xs.iter().any(|x| lower.contains(x));
+        if has(&["more than one item matches"]) {
+            OpError::AmbiguousItem(name())
+        } else if has(&["isn't an item", "doesn't seem to be an item"]) {
+            OpError::ItemNotFound(name())
+        } else if has(&["isn't a vault", "doesn't seem to be a vault"]) {
+            OpError::VaultNotFound(name())
+        } else if has(&["401", "unauthorized", "authentication required"]) {
+            match action {
+                OpAction::SignIn => OpError::WrongPassword,
+                OpAction::Other => OpError::SessionExpired,
+            }
+        } else if has(&["session expired", "invalid session token"]) {
+            OpError::SessionExpired
+        } else if has(&["not currently signed in", "you are not signed in"]) {
+            OpError::NotSignedIn
+        } else {
+            let code = code.map_or_else(|| "killed".to_string(), |c| c.to_string());
+            OpError::UnexpectedOutput(format!("exit status {}: {}", code, msg))
+        }
+    }
+
+    /// the error if op failed
+    pub(crate) fn check(out: &Output, action: OpAction) -> Result<(), Self> {
+        if out.status.success() {
+            return Ok(());
+        }
+        Err(Self::classify(
+            out.status.code(),
+            &String::from_utf8_lossy(&out.stderr),
+            action,
+        ))
+    }
+
+    /// the error of running the binary
+    pub(crate) fn from_spawn(bin_filename: &str, err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => OpError::BinaryNotFound(bin_filename.to_string()),
+            _ => OpError::Io(format!("{}: {}", bin_filename, err)),
+        }
+    }
+
+    /// the error of talking to the running binary
+    pub(crate) fn from_io(err: std::io::Error) -> Self {
+        OpError::Io(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify_v1() {
+        let classify = |s: &str| OpError::classify(Some(1), s, OpAction::Other);
+        assert_eq!(
+            OpError::NotSignedIn,
+            classify("[ERROR] 2021/11/20 10:14:04 You are not currently signed in. Please run `op signin --help` for instructions\n")
+        );
+        assert_eq!(
+            OpError::SessionExpired,
+            classify("[ERROR] 2021/11/20 10:14:04 Invalid session token\n")
+        );
+        assert_eq!(
+            OpError::ItemNotFound("quake".to_string()),
+            classify("[ERROR] 2021/11/20 10:14:04 \"quake\" doesn't seem to be an item. Specify the item with its UUID, name, or domain.\n")
+        );
+        assert_eq!(
+            OpError::VaultNotFound("Work".to_string()),
+            classify("[ERROR] 2021/11/20 10:14:04 \"Work\" doesn't seem to be a vault in this account. Specify the vault with its UUID or name.\n")
+        );
+        assert_eq!(
+            OpError::WrongPassword,
+            OpError::classify(
+                Some(1),
+                "[ERROR] 2021/11/20 10:14:04 401: Authentication required.\n",
+                OpAction::SignIn
+            )
+        );
+    }
+
+    #[test]
+    fn test_classify_v2() {
+        let classify = |s: &str| OpError::classify(Some(1), s, OpAction::Other);
+        assert_eq!(
+            OpError::AmbiguousItem("doom".to_string()),
+            classify("[ERROR] 2022/01/08 02:51:50 More than one item matches \"doom\". Try again and specify the item by its ID:\n\t* for the item \"doom\" in vault Private: 4ngqoxbzeljmtcedrgdwywqhia\n")
+        );
+        assert_eq!(
+            OpError::ItemNotFound("quake".to_string()),
+            classify("[ERROR] 2022/01/08 02:51:50 \"quake\" isn't an item. Specify the item with its UUID, name, or domain.\n")
+        );
+        assert_eq!(
+            OpError::VaultNotFound("Work".to_string()),
+            classify("[ERROR] 2022/01/08 02:51:50 \"Work\" isn't a vault in this account. Specify the vault with its ID or name.\n")
+        );
+        assert_eq!(
+            OpError::SessionExpired,
+            classify("[ERROR] 2022/01/08 02:51:50 You aren't authorized to perform this action: (401) Unauthorized\n")
+        );
+        assert_eq!(
+            OpError::WrongPassword,
+            OpError::classify(
+                Some(1),
+                "[ERROR] 2022/01/08 02:51:50 (401) Unauthorized: You aren't authorized to perform this action.\n",
+                OpAction::SignIn
+            )
+        );
+        assert_eq!(
+            OpError::UnexpectedOutput("exit status 2: unknown flag: --bfg".to_string()),
+            OpError::classify(Some(2), "unknown flag: --bfg\n", OpAction::Other)
+        );
+    }
+}
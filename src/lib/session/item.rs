@@ -0,0 +1,411 @@
+// to model an item as printed by `op item get --format json` (2.x); `op get item` (1.x)
+// prints a different document (details/overview, single-letter keys in the sections),
+// which is mapped onto the same model:
+// - details.fields (username, password) and notesPlain become the fields with a purpose;
+// - sections[].fields become the fields in that section, keyed by n (id), t (label),
+//   k (kind) and v (value);
+// - overview carries the urls and the tags; 1.x has no secret references.
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::error::OpError;
+use crate::ReleaseNoteUrl;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    pub id: String,
+    pub title: String,
+    /// e.g. LOGIN, PASSWORD, SECURE_NOTE
+    pub category: String,
+    pub vault: Vault,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub urls: Vec<ItemUrl>,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vault {
+    pub id: String,
+    /// not printed by 1.x
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemUrl {
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+    pub href: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Field {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub purpose: Option<FieldPurpose>,
+    pub label: String,
+    #[serde(default)]
+    pub section: Option<Section>,
+    /// absent if the field is empty
+    #[serde(default)]
+    pub value: Option<String>,
+    /// e.g. op://Private/doom/password; not printed by 1.x
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    pub id: String,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FieldType {
+    String,
+    Concealed,
+    Email,
+    Url,
+    Otp,
+    Phone,
+    Date,
+    MonthYear,
+    Menu,
+    #[serde(other)]
+    Unknown,
+}
+
+/// the built-in fields of a login item
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FieldPurpose {
+    Username,
+    Password,
+    Notes,
+}
+
+impl Item {
+    /// parse the output of `op get item` (1.x) or `op item get --format json` (2.x)
+    pub fn from_json(major: &ReleaseNoteUrl, s: &str) -> anyhow::Result<Self> {
+        match major {
+            ReleaseNoteUrl::V1 => Ok(serde_json::from_str::<V1Item>(s)?.into()),
+            ReleaseNoteUrl::V2 => Ok(serde_json::from_str(s)?),
+        }
+    }
+
+    /// the field by label or id, ignoring the case; the label wins
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|f| f.label.eq_ignore_ascii_case(name))
+            .or_else(|| self.fields.iter().find(|f| f.id.eq_ignore_ascii_case(name)))
+    }
+
+    /// the values of the fields, in the order of the names; an empty field gives ""
+    pub fn field_values(&self, names: &[&str]) -> Result<Vec<String>, OpError> {
+        names
+            .iter()
+            .map(|name| {
+                self.field(name)
+                    .map(|f| f.value.clone().unwrap_or_default())
+                    .ok_or_else(|| OpError::FieldNotFound {
+                        item: self.title.clone(),
+                        field: name.to_string(),
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct V1Item {
+    uuid: String,
+    template_uuid: String,
+    vault_uuid: String,
+    #[serde(default)]
+    details: V1Details,
+    #[serde(default)]
+    overview: V1Overview,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct V1Details {
+    #[serde(default)]
+    fields: Vec<V1Field>,
+    #[serde(default)]
+    notes_plain: Option<String>,
+    #[serde(default)]
+    sections: Vec<V1Section>,
+}
+
+#[derive(Deserialize)]
+struct V1Field {
+    #[serde(default)]
+    designation: String,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    field_type: String,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct V1Section {
+    name: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    fields: Vec<V1SectionField>,
+}
+
+#[derive(Deserialize)]
+struct V1SectionField {
+    k: String,
+    n: String,
+    #[serde(default)]
+    t: String,
+    #[serde(default)]
+    v: Option<serde_json::Value>,
+}
+
+#[derive(Default, Deserialize)]
+struct V1Overview {
+    #[serde(default)]
+    title: String,
+    #[serde(default, rename = "URLs")]
+    urls: Vec<V1Url>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct V1Url {
+    #[serde(default)]
+    l: Option<String>,
+    u: String,
+}
+
+const TOTP_PREFIX: &str = "TOTP_";
+
+/// the category of the 1.x template
+fn v1_category(template_uuid: &str) -> String {
+    match template_uuid {
+        "001" => "LOGIN",
+        "002" => "CREDIT_CARD",
+        "003" => "SECURE_NOTE",
+        "004" => "IDENTITY",
+        "005" => "PASSWORD",
+        "006" => "DOCUMENT",
+        other => other,
+    }
+    .to_string()
+}
+
+impl From<V1Item> for Item {
+    fn from(v1: V1Item) -> Self {
+        let mut fields: Vec<Field> = v1
+            .details
+            .fields
+            .into_iter()
+            .map(|f| Field {
+                purpose: match f.designation.as_str() {
+                    "username" => Some(FieldPurpose::Username),
+                    "password" => Some(FieldPurpose::Password),
+                    _ => None,
+                },
+                field_type: match f.field_type.as_str() {
+                    "T" | "A" => FieldType::String,
+                    "P" => FieldType::Concealed,
+                    "E" => FieldType::Email,
+                    "U" => FieldType::Url,
+                    _ => FieldType::Unknown,
+                },
+                label: if f.designation.is_empty() {
+                    f.name.clone()
+                } else {
+                    f.designation
+                },
+                id: f.name,
+                section: None,
+                value: f.value,
+                reference: None,
+            })
+            .collect();
+        fields.push(Field {
+            id: "notesPlain".to_string(),
+            field_type: FieldType::String,
+            purpose: Some(FieldPurpose::Notes),
+            label: "notesPlain".to_string(),
+            section: None,
+            value: v1.details.notes_plain.filter(|s| !s.is_empty()),
+            reference: None,
+        });
+        for sect in v1.details.sections {
+            let section = Section {
+                id: sect.name,
+                label: sect.title,
+            };
+            fields.extend(sect.fields.into_iter().map(|f| Field {
+                field_type: match f.k.as_str() {
+                    "string" => FieldType::String,
+                    // the one-time passwords are concealed fields named TOTP_<id>
+                    "concealed" if f.n.starts_with(TOTP_PREFIX) => FieldType::Otp,
+                    "concealed" => FieldType::Concealed,
+                    "email" => FieldType::Email,
+                    "URL" => FieldType::Url,
+                    "phone" => FieldType::Phone,
+                    "date" => FieldType::Date,
+                    "monthYear" => FieldType::MonthYear,
+                    "menu" => FieldType::Menu,
+                    _ => FieldType::Unknown,
+                },
+                id: f.n.trim_start_matches(TOTP_PREFIX).to_string(),
+                purpose: None,
+                label: f.t,
+                section: Some(section.clone()),
+                // dates and months are numbers
+                value: f.v.map(|v| match v {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                }),
+                reference: None,
+            }));
+        }
+        let primary = v1.overview.url;
+        Item {
+            id: v1.uuid,
+            title: v1.overview.title,
+            category: v1_category(&v1.template_uuid),
+            vault: Vault {
+                id: v1.vault_uuid,
+                name: None,
+            },
+            tags: v1.overview.tags,
+            urls: v1
+                .overview
+                .urls
+                .into_iter()
+                .map(|u| ItemUrl {
+                    label: u.l,
+                    primary: primary.as_deref() == Some(u.u.as_str()),
+                    href: u.u,
+                })
+                .collect(),
+            fields,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join("items")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_v2_item() {
+        let item = Item::from_json(&ReleaseNoteUrl::V2, &fixture("item_v2.json")).unwrap();
+        assert_eq!("doom", item.title);
+        assert_eq!("LOGIN", item.category);
+        assert_eq!(Some("Private"), item.vault.name.as_deref());
+        assert_eq!(vec!["games", "id software"], item.tags);
+        assert_eq!(2, item.urls.len());
+        assert!(item.urls[0].primary);
+        assert!(!item.urls[1].primary);
+
+        let password = item.field("password").unwrap();
+        assert_eq!(FieldType::Concealed, password.field_type);
+        assert_eq!(Some(FieldPurpose::Password), password.purpose);
+        assert_eq!(
+            Some("op://Private/doom/password"),
+            password.reference.as_deref()
+        );
+        let otp = item.field("One-Time Password").unwrap();
+        assert_eq!(FieldType::Otp, otp.field_type);
+        assert_eq!(
+            Some("cheats"),
+            otp.section.as_ref().and_then(|s| s.label.as_deref())
+        );
+        assert_eq!(None, item.field("notesPlain").unwrap().value);
+    }
+
+    #[test]
+    fn test_parse_v1_item() {
+        let v1 = Item::from_json(&ReleaseNoteUrl::V1, &fixture("item_v1.json")).unwrap();
+        let v2 = Item::from_json(&ReleaseNoteUrl::V2, &fixture("item_v2.json")).unwrap();
+        assert_eq!(v2.id, v1.id);
+        assert_eq!(v2.title, v1.title);
+        assert_eq!(v2.category, v1.category);
+        assert_eq!(v2.vault.id, v1.vault.id);
+        assert_eq!(None, v1.vault.name);
+        assert_eq!(v2.tags, v1.tags);
+        assert_eq!(
+            v2.urls
+                .iter()
+                .map(|u| (&u.href, u.primary))
+                .collect::<Vec<_>>(),
+            v1.urls
+                .iter()
+                .map(|u| (&u.href, u.primary))
+                .collect::<Vec<_>>()
+        );
+        for f in &v2.fields {
+            let g = v1.field(&f.label).unwrap();
+            assert_eq!(
+                (&f.id, f.field_type, f.purpose, &f.value),
+                (&g.id, g.field_type, g.purpose, &g.value)
+            );
+            assert_eq!(
+                f.section.as_ref().map(|s| &s.id),
+                g.section.as_ref().map(|s| &s.id)
+            );
+            assert_eq!(None, g.reference);
+        }
+    }
+
+    #[test]
+    fn test_field_values() {
+        let item = Item::from_json(&ReleaseNoteUrl::V2, &fixture("item_v2.json")).unwrap();
+        assert_eq!(
+            vec!["doomguy", "iddqd,idkfa", "idclip\nidspispopd", ""],
+            item.field_values(&[
+                "username",
+                "PASSWORD",
+                "3mb4rwatqsxqfgqvaqkvmtt6wq",
+                "notesPlain"
+            ])
+            .unwrap()
+        );
+        assert_eq!(
+            Err(OpError::FieldNotFound {
+                item: "doom".to_string(),
+                field: "bfg".to_string()
+            }),
+            item.field_values(&["username", "bfg"])
+        );
+    }
+}
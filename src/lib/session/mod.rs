@@ -1,7 +1,17 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod error;
+mod item;
+mod password;
 mod signin;
 mod types;
 
+pub use error::OpError;
+pub use item::{Field, FieldPurpose, FieldType, Item, ItemUrl, Section, Vault};
+pub use password::{
+    AskpassPassword, EnvPassword, FdPassword, FnPassword, PasswordProvider, TtyPrompt, PASSWORD_ENV,
+};
 pub use signin::{
     local_accounts_v1, local_accounts_v2, sign_in_shorthand_v1, sign_in_shorthand_v2,
 };
-pub use types::{Session, SessionConfig};
+pub use types::{Account, Session, SessionCode, SessionConfig, SERVICE_ACCOUNT_TOKEN_ENV};
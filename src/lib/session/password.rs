@@ -0,0 +1,257 @@
+// to get the master password for signing in, without a terminal if needed:
+// - TtyPrompt asks on the terminal (the default);
+// - EnvPassword reads an env var, $OPENV_PASSWORD by default;
+// - FdPassword reads the first line of stdin, or of a file (or pipe) handed over by the
+//   caller; on unix an inherited descriptor is opened through /dev/fd, so a descriptor
+//   that isn't open is an error rather than undefined behaviour;
+// - AskpassPassword runs a program (like $SSH_ASKPASS) with the prompt as the argument and
+//   reads the first line it prints;
+// - FnPassword calls a closure, e.g. for a gui or a test.
+// the providers are called on the blocking thread pool by the async api.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use rpassword::prompt_password_stdout;
+
+pub const PASSWORD_ENV: &str = "OPENV_PASSWORD";
+
+pub trait PasswordProvider: fmt::Debug + Send + Sync {
+    /// the master password of the account (shorthand)
+    fn password(&self, shorthand: &str) -> anyhow::Result<String>;
+}
+
+fn prompt(shorthand: &str) -> String {
+    format!(
+        "Your 1Password master password for shorthand({}):",
+        shorthand
+    )
+}
+
+/// the first line without the line break
+fn first_line(s: &str) -> String {
+    let line = s.split('\n').next().unwrap_or_default();
+    line.strip_suffix('\r').unwrap_or(line).to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TtyPrompt;
+
+impl PasswordProvider for TtyPrompt {
+    fn password(&self, shorthand: &str) -> anyhow::Result<String> {
+        Ok(prompt_password_stdout(&prompt(shorthand))?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvPassword {
+    pub var: String,
+}
+
+impl EnvPassword {
+    pub fn new(var: &str) -> Self {
+        Self {
+            var: var.to_string(),
+        }
+    }
+}
+
+impl Default for EnvPassword {
+    fn default() -> Self {
+        Self::new(PASSWORD_ENV)
+    }
+}
+
+impl PasswordProvider for EnvPassword {
+    fn password(&self, _shorthand: &str) -> anyhow::Result<String> {
+        std::env::var(&self.var).map_err(|_| anyhow!("{} is not set.", self.var))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FdPassword {
+    /// stdin if none; owned by the provider and closed with it
+    file: Option<Mutex<File>>,
+}
+
+impl FdPassword {
+    pub fn stdin() -> Self {
+        Self { file: None }
+    }
+
+    /// the file (or pipe) handed over by the caller
+    pub fn from_file(file: File) -> Self {
+        Self {
+            file: Some(Mutex::new(file)),
+        }
+    }
+
+    /// the descriptor handed over by the caller
+    #[cfg(target_family = "unix")]
+    pub fn from_fd(fd: std::os::unix::io::OwnedFd) -> Self {
+        Self::from_file(File::from(fd))
+    }
+
+    /// the descriptor inherited from the parent, e.g. --password-fd 3; an error if it isn't
+    /// open
+    #[cfg(target_family = "unix")]
+    pub fn open_fd(fd: i32) -> anyhow::Result<Self> {
+        let file = File::open(format!("/dev/fd/{}", fd))
+            .map_err(|e| anyhow!("can't read the password from fd {}: {}", fd, e))?;
+        Ok(Self::from_file(file))
+    }
+
+    #[cfg(target_family = "windows")]
+    pub fn open_fd(fd: i32) -> anyhow::Result<Self> {
+        Err(anyhow!("reading the password from fd {} needs unix.", fd))
+    }
+}
+
+/// byte by byte, not to consume past the line
+fn read_line_of(file: &mut File) -> anyhow::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while file.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        line.push(byte[0]);
+    }
+    Ok(first_line(&String::from_utf8(line)?))
+}
+
+impl PasswordProvider for FdPassword {
+    fn password(&self, _shorthand: &str) -> anyhow::Result<String> {
+        match &self.file {
+            Some(file) => {
+                let mut file = file
+                    .lock()
+                    .map_err(|_| anyhow!("the password file is poisoned."))?;
+                read_line_of(&mut file)
+            }
+            None => {
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line)?;
+                Ok(first_line(&line))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AskpassPassword {
+    pub program: PathBuf,
+}
+
+impl AskpassPassword {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl PasswordProvider for AskpassPassword {
+    fn password(&self, shorthand: &str) -> anyhow::Result<String> {
+        let out = Command::new(&self.program)
+            .arg(prompt(shorthand))
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !out.status.success() {
+            return Err(anyhow!(
+                "askpass {} failed: {}",
+                self.program.display(),
+                out.status
+            ));
+        }
+        Ok(first_line(&String::from_utf8(out.stdout)?))
+    }
+}
+
+pub struct FnPassword<F>(pub F);
+
+impl<F> fmt::Debug for FnPassword<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FnPassword")
+    }
+}
+
+impl<F> PasswordProvider for FnPassword<F>
+where
+    F: Fn(&str) -> anyhow::Result<String> + Send + Sync,
+{
+    fn password(&self, shorthand: &str) -> anyhow::Result<String> {
+        (self.0)(shorthand)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_env_password() {
+        std::env::set_var("OPENV_TEST_PASSWORD", "iddqd idkfa");
+        let provider = EnvPassword::new("OPENV_TEST_PASSWORD");
+        assert_eq!("iddqd idkfa", provider.password("my").unwrap());
+        assert!(EnvPassword::new("OPENV_TEST_NO_PASSWORD")
+            .password("my")
+            .is_err());
+    }
+
+    #[test]
+    fn test_fn_password() {
+        let provider = FnPassword(|shorthand: &str| Ok(format!("{}:iddqd", shorthand)));
+        assert_eq!("my:iddqd", provider.password("my").unwrap());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_fd_password() {
+        use std::io::Write;
+        use std::os::unix::io::{AsRawFd, OwnedFd};
+
+        let filename = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join("password_fd");
+        std::fs::create_dir_all(filename.parent().unwrap()).unwrap();
+        std::fs::File::create(&filename)
+            .unwrap()
+            .write_all(b"iddqd\r\nidkfa\n")
+            .unwrap();
+        let file = File::open(&filename).unwrap();
+        let provider = FdPassword::from_fd(OwnedFd::from(file));
+        assert_eq!("iddqd", provider.password("my").unwrap());
+        assert_eq!("idkfa", provider.password("my").unwrap());
+
+        // an inherited descriptor is opened anew; one that isn't open is refused
+        let file = File::open(&filename).unwrap();
+        let provider = FdPassword::open_fd(file.as_raw_fd()).unwrap();
+        assert_eq!("iddqd", provider.password("my").unwrap());
+        drop(file);
+        assert!(FdPassword::open_fd(987_654).is_err());
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_askpass_password() {
+        use crate::session::signin::test::fake_op;
+
+        // prints the prompt back
+        let askpass = fake_op("password_askpass", "echo \"$1\"");
+        let provider = AskpassPassword::new(&askpass);
+        assert_eq!(
+            "Your 1Password master password for shorthand(my):",
+            provider.password("my").unwrap()
+        );
+        let failing = fake_op("password_askpass_failing", "exit 1");
+        assert!(AskpassPassword::new(&failing).password("my").is_err());
+        std::fs::remove_dir_all(askpass.parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(failing.parent().unwrap()).unwrap();
+    }
+}
@@ -1,124 +1,276 @@
 use crate::ReleaseNoteUrl;
-use anyhow::anyhow;
-use rpassword::prompt_password_stdout;
-use std::io::{Read, Write};
 use std::process::{Command, Stdio};
+use tokio::io::AsyncWriteExt;
 
+use crate::session::error::{OpAction, OpError};
 use crate::session::types::*;
 
-/// list all the accounts configured in the host system; only work with 1password cli 1.x
-pub fn local_accounts_v1(conf: &SessionConfig) -> anyhow::Result<Vec<Account>> {
-    let mut proc = Command::new(&conf.bin_filename)
-        .stdout(Stdio::piped())
-        .arg("signin")
-        .arg("-l")
-        .spawn()?;
-    let mut stdout = proc
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("local accounts: fail to take stdout"))?;
-    let mut out_str = String::with_capacity(1024);
-    stdout.read_to_string(&mut out_str)?;
-    drop(stdout);
-    Ok(Account::from_descriptions(&out_str))
+/// `op signin -l` (1.x) or `op account list` (2.x)
+pub(crate) fn local_accounts_command(conf: &SessionConfig, major: &ReleaseNoteUrl) -> Command {
+    let mut cmd = Command::new(&conf.bin_filename);
+    cmd.stdout(Stdio::piped());
+    match major {
+        ReleaseNoteUrl::V1 => cmd.arg("signin").arg("-l"),
+        ReleaseNoteUrl::V2 => cmd.arg("account").arg("list"),
+    };
+    cmd
 }
 
-/// list all the accounts configured in the host system; only work with 1password cli 2.x
-pub fn local_accounts_v2(conf: &SessionConfig) -> anyhow::Result<Vec<Account>> {
-    let mut proc = Command::new(&conf.bin_filename)
+/// `op signin` printing the session (1.x: the raw code, 2.x: the export line);
+/// the master password is written to stdin, stderr is kept to classify the failure
+pub(crate) fn sign_in_command(conf: &SessionConfig, major: &ReleaseNoteUrl) -> Command {
+    let mut cmd = Command::new(&conf.bin_filename);
+    cmd.stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .arg("account")
-        .arg("list")
-        .spawn()?;
-    let mut stdout = proc
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("local accounts: fail to take stdout"))?;
-    let mut out_str = String::with_capacity(1024);
-    stdout.read_to_string(&mut out_str)?;
-    drop(stdout);
-    Ok(Account::from_descriptions(&out_str))
+        .stderr(Stdio::piped());
+    match major {
+        ReleaseNoteUrl::V1 => cmd.arg("signin").arg("-r").arg(&conf.shorthand),
+        ReleaseNoteUrl::V2 => cmd
+            .arg("signin")
+            .arg("-f")
+            .arg("--account")
+            .arg(&conf.shorthand),
+    };
+    cmd
 }
 
-/// this signin function works with 1password cli 1.x
-pub fn sign_in_shorthand_v1(conf: &SessionConfig) -> anyhow::Result<Session> {
-    let mut proc = Command::new(&conf.bin_filename)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .arg("signin")
-        .arg("-r")
-        .arg(&conf.shorthand)
-        .spawn()?;
-    let mut stdin = proc
-        .stdin
-        .take()
-        .ok_or_else(|| anyhow!("signin: fail to take stdin"))?;
-    let mut stdout = proc
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("signin: fail to take stdout"))?;
-    let mut out_str = "".to_string();
-    write!(
-        stdin,
-        "{}",
-        prompt_password_stdout(&format!(
-            "Your 1Password master password for shorthand({}):",
-            &conf.shorthand
-        ))?
-    )?;
-    drop(stdin);
-    stdout.read_to_string(&mut out_str)?;
-    drop(stdout);
+/// the session from the output of `op signin`
+pub(crate) fn parse_session(
+    conf: &SessionConfig,
+    major: ReleaseNoteUrl,
+    out_str: &str,
+) -> Result<Session, OpError> {
+    let unexpected = || OpError::UnexpectedOutput(out_str.trim().to_string());
+    let session_code = match major {
+        ReleaseNoteUrl::V1 if out_str.trim().is_empty() => return Err(unexpected()),
+        ReleaseNoteUrl::V1 => SessionCode::V1PlainString(out_str.trim().to_string()),
+        // export OP_SESSION_xxx="yyy"\n# This command is meant to be used with your shell's eval function...
+        ReleaseNoteUrl::V2 => {
+            let first = out_str.split('#').next().unwrap_or_default();
+            let kv = first.split("export ").last().unwrap_or_default().trim();
+            let (key, value) = kv.split_once('=').ok_or_else(unexpected)?;
+            SessionCode::V2KeyValuePair {
+                key: key.to_string(),
+                value: value.trim_matches('"').to_string(),
+            }
+        }
+    };
     Ok(Session {
         bin_filename: conf.bin_filename.clone(),
         shorthand: conf.shorthand.clone(),
-        session_code: SessionCode::V1PlainString(out_str.trim().to_string()),
-        major_version: ReleaseNoteUrl::V1,
+        session_code,
+        major_version: major,
     })
 }
 
-/// this signin function works with 1password cli 2.x
-pub fn sign_in_shorthand_v2(conf: &SessionConfig) -> anyhow::Result<Session> {
-    let mut proc = Command::new(&conf.bin_filename)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .arg("signin")
-        .arg("-f")
-        .arg("--account")
-        .arg(&conf.shorthand)
-        .spawn()?;
+async fn local_accounts(
+    conf: &SessionConfig,
+    major: ReleaseNoteUrl,
+) -> Result<Vec<Account>, OpError> {
+    let out = tokio::process::Command::from(local_accounts_command(conf, &major))
+        .output()
+        .await
+        .map_err(|e| OpError::from_spawn(&conf.bin_filename, e))?;
+    OpError::check(&out, OpAction::Other)?;
+    Ok(Account::from_descriptions(&String::from_utf8_lossy(
+        &out.stdout,
+    )))
+}
+
+/// list all the accounts configured in the host system; only work with 1password cli 1.x
+pub async fn local_accounts_v1(conf: &SessionConfig) -> Result<Vec<Account>, OpError> {
+    local_accounts(conf, ReleaseNoteUrl::V1).await
+}
+
+/// list all the accounts configured in the host system; only work with 1password cli 2.x
+pub async fn local_accounts_v2(conf: &SessionConfig) -> Result<Vec<Account>, OpError> {
+    local_accounts(conf, ReleaseNoteUrl::V2).await
+}
+
+async fn sign_in_shorthand(
+    conf: &SessionConfig,
+    major: ReleaseNoteUrl,
+) -> Result<Session, OpError> {
+    let (provider, shorthand) = (conf.password.clone(), conf.shorthand.clone());
+    // e.g. reading the terminal blocks
+    let password = tokio::task::spawn_blocking(move || provider.password(&shorthand))
+        .await
+        .map_err(|e| OpError::NoPassword(e.to_string()))?
+        .map_err(|e| OpError::NoPassword(e.to_string()))?;
+    let mut proc = tokio::process::Command::from(sign_in_command(conf, &major))
+        .spawn()
+        .map_err(|e| OpError::from_spawn(&conf.bin_filename, e))?;
     let mut stdin = proc
         .stdin
         .take()
-        .ok_or_else(|| anyhow!("signin: fail to take stdin"))?;
-    let mut stdout = proc
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("signin: fail to take stdout"))?;
-    let mut out_str = "".to_string();
-    write!(
-        stdin,
-        "{}",
-        prompt_password_stdout(&format!(
-            "Your 1Password master password for shorthand({}):",
-            &conf.shorthand
-        ))?
-    )?;
+        .ok_or_else(|| OpError::Io("signin: fail to take stdin".to_string()))?;
+    stdin
+        .write_all(password.as_bytes())
+        .await
+        .map_err(OpError::from_io)?;
     drop(stdin);
-    stdout.read_to_string(&mut out_str)?;
-    drop(stdout);
-    let first = out_str.split('#').into_iter().next().unwrap();
-    let kv = first.split("export ").into_iter().last().unwrap().trim();
-    let segments: Vec<&str> = kv.split('=').collect();
-    let key = segments[0];
-    let value = segments[1].trim_matches('"');
-    Ok(Session {
-        bin_filename: conf.bin_filename.clone(),
-        shorthand: conf.shorthand.clone(),
-        session_code: SessionCode::V2KeyValuePair {
-            key: key.to_string(),
-            value: value.to_string(),
-        },
-        major_version: ReleaseNoteUrl::V2,
-    })
+    let out = proc.wait_with_output().await.map_err(OpError::from_io)?;
+    OpError::check(&out, OpAction::SignIn)?;
+    parse_session(conf, major, &String::from_utf8_lossy(&out.stdout))
+}
+
+/// this signin function works with 1password cli 1.x
+pub async fn sign_in_shorthand_v1(conf: &SessionConfig) -> Result<Session, OpError> {
+    sign_in_shorthand(conf, ReleaseNoteUrl::V1).await
+}
+
+/// this signin function works with 1password cli 2.x
+pub async fn sign_in_shorthand_v2(conf: &SessionConfig) -> Result<Session, OpError> {
+    sign_in_shorthand(conf, ReleaseNoteUrl::V2).await
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use tokio::runtime::Runtime;
+
+    use super::*;
+    use crate::session::password::{FnPassword, TtyPrompt};
+
+    /// a fake op that prints the script output regardless of the arguments
+    #[cfg(target_family = "unix")]
+    pub(crate) fn fake_op(name: &str, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let dirname = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("tmp")
+            .join(name);
+        let _dont_care = std::fs::remove_dir_all(&dirname);
+        std::fs::create_dir_all(&dirname).unwrap();
+        let filename = dirname.join("op");
+        std::fs::write(&filename, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&filename, std::fs::Permissions::from_mode(0o755)).unwrap();
+        filename
+    }
+
+    #[test]
+    fn test_parse_session() {
+        let conf = SessionConfig {
+            bin_filename: "op".to_string(),
+            shorthand: "my".to_string(),
+            password: Arc::new(TtyPrompt),
+        };
+        let out = "export OP_SESSION_my=\"abc=\"\n# This command is meant to be used with your shell's eval function.\n";
+        let sess = parse_session(&conf, ReleaseNoteUrl::V2, out).unwrap();
+        assert_eq!(
+            SessionCode::V2KeyValuePair {
+                key: "OP_SESSION_my".to_string(),
+                value: "abc=".to_string()
+            },
+            sess.session_code
+        );
+        let sess = parse_session(&conf, ReleaseNoteUrl::V1, "abc\n").unwrap();
+        assert_eq!(("OP_SESSION_my".to_string(), "abc".to_string()), sess.env());
+        assert!(parse_session(&conf, ReleaseNoteUrl::V2, "[ERROR] no").is_err());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_local_accounts_v2() {
+        let op = fake_op(
+            "session_local_accounts_v2",
+            "echo 'SHORTHAND  URL                 EMAIL             USER ID'\necho 'my         my.1password.com    doomguy@doom.org  ABCDEF'",
+        );
+        let conf = SessionConfig {
+            bin_filename: op.to_string_lossy().into_owned(),
+            shorthand: String::new(),
+            password: Arc::new(TtyPrompt),
+        };
+        let rt = Runtime::new().unwrap();
+        let accounts = rt.block_on(local_accounts_v2(&conf)).unwrap();
+        assert_eq!(1, accounts.len());
+        assert_eq!("doomguy@doom.org", accounts[0].email);
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_local_accounts_errors() {
+        let conf = SessionConfig {
+            bin_filename: "/nowhere/op".to_string(),
+            shorthand: String::new(),
+            password: Arc::new(TtyPrompt),
+        };
+        let rt = Runtime::new().unwrap();
+        let err = rt.block_on(local_accounts_v2(&conf)).unwrap_err();
+        assert_eq!(OpError::BinaryNotFound("/nowhere/op".to_string()), err);
+        let err = parse_session(&conf, ReleaseNoteUrl::V1, "\n").unwrap_err();
+        assert!(matches!(err, OpError::UnexpectedOutput(_)));
+        let err = parse_session(&conf, ReleaseNoteUrl::V2, "OP_SESSION_my\n").unwrap_err();
+        assert!(matches!(err, OpError::UnexpectedOutput(_)));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_sign_in_with_provider() {
+        // the session for the right password, a 401 otherwise
+        let op = fake_op(
+            "session_sign_in_provider",
+            concat!(
+                "read -r password\n",
+                "[ \"$password\" = iddqd ] || { echo '[ERROR] 2022/01/08 02:51:50 (401) Unauthorized' >&2; exit 1; }\n",
+                "echo \"export OP_SESSION_$4=\\\"abc\\\"\""
+            ),
+        );
+        let conf = |password: &'static str| SessionConfig {
+            bin_filename: op.to_string_lossy().into_owned(),
+            shorthand: "my".to_string(),
+            password: Arc::new(FnPassword(move |_: &str| Ok(password.to_string()))),
+        };
+        let rt = Runtime::new().unwrap();
+        let sess = rt.block_on(sign_in_shorthand_v2(&conf("iddqd"))).unwrap();
+        assert_eq!(("OP_SESSION_my".to_string(), "abc".to_string()), sess.env());
+        let err = rt
+            .block_on(sign_in_shorthand_v2(&conf("idkfa")))
+            .unwrap_err();
+        assert_eq!(OpError::WrongPassword, err);
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_sign_in_failures() {
+        // 1.x prints the failure on stderr and nothing on stdout
+        let op = fake_op(
+            "session_sign_in_failures",
+            concat!(
+                "read -r password\n",
+                "[ \"$3\" = my ] || { echo \"[ERROR] 2021/11/20 10:14:04 No account found for shorthand $3\" >&2; exit 1; }\n",
+                "echo '[ERROR] 2021/11/20 10:14:04 401: Authentication required.' >&2\n",
+                "exit 1"
+            ),
+        );
+        let conf = |shorthand: &str| SessionConfig {
+            bin_filename: op.to_string_lossy().into_owned(),
+            shorthand: shorthand.to_string(),
+            password: Arc::new(FnPassword(|_: &str| Ok("iddqd".to_string()))),
+        };
+        let rt = Runtime::new().unwrap();
+        let err = rt.block_on(sign_in_shorthand_v1(&conf("my"))).unwrap_err();
+        assert_eq!(OpError::WrongPassword, err);
+        let err = rt
+            .block_on(sign_in_shorthand_v1(&conf("doom")))
+            .unwrap_err();
+        assert_eq!(
+            OpError::UnexpectedOutput(
+                "exit status 1: No account found for shorthand doom".to_string()
+            ),
+            err
+        );
+
+        // the password never reaches op
+        let failing = SessionConfig {
+            password: Arc::new(FnPassword(|_: &str| Err(anyhow::anyhow!("no tty")))),
+            ..conf("my")
+        };
+        let err = rt.block_on(sign_in_shorthand_v1(&failing)).unwrap_err();
+        assert_eq!(OpError::NoPassword("no tty".to_string()), err);
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
 }
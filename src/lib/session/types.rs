@@ -1,10 +1,17 @@
-use crate::ReleaseNoteUrl;
-use std::process::Command;
+use crate::session::error::{OpAction, OpError};
+use crate::session::item::Item;
+use crate::session::password::PasswordProvider;
+use crate::{LocalVersion, ReleaseNoteUrl};
+use std::process::{Command, Output};
 use std::str::FromStr;
+use std::sync::Arc;
 
+#[derive(Debug, Clone)]
 pub struct SessionConfig {
     pub bin_filename: String,
     pub shorthand: String,
+    /// where the master password comes from when signing in
+    pub password: Arc<dyn PasswordProvider>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -18,9 +25,17 @@ pub struct Session {
 #[derive(Debug, PartialEq)]
 pub enum SessionCode {
     V1PlainString(String),
-    V2KeyValuePair { key: String, value: String },
+    V2KeyValuePair {
+        key: String,
+        value: String,
+    },
+    /// the token of a service account (2.x); no signin needed
+    ServiceAccount(String),
 }
 
+/// the env var that carries the service account token to op 2.x
+pub const SERVICE_ACCOUNT_TOKEN_ENV: &str = "OP_SERVICE_ACCOUNT_TOKEN";
+
 #[derive(Debug)]
 pub struct Account {
     pub shorthand: String, // e.g. iddqd
@@ -29,39 +44,87 @@ pub struct Account {
 }
 
 impl Session {
-    pub fn item_fields(&self, item: &str, fields: &[&str]) -> anyhow::Result<Vec<String>> {
+    /// the session of a service account with the binary, which must be 2.x
+    pub fn from_service_account_token(
+        local_version: &LocalVersion,
+        token: &str,
+    ) -> anyhow::Result<Self> {
+        let major_version = ReleaseNoteUrl::for_version(&local_version.version);
+        if major_version != ReleaseNoteUrl::V2 {
+            return Err(anyhow::anyhow!(
+                "service accounts need op 2.x, not {}.",
+                local_version.version
+            ));
+        }
+        Ok(Session {
+            bin_filename: local_version.path.clone(),
+            shorthand: String::new(),
+            session_code: SessionCode::ServiceAccount(token.to_string()),
+            major_version,
+        })
+    }
+
+    /// the env var that carries the session to the op binary
+    pub fn env(&self) -> (String, String) {
         match &self.session_code {
-            SessionCode::V1PlainString(session_code) => {
-                let out = Command::new(&self.bin_filename)
-                    .env(format!("OP_SESSION_{}", self.shorthand), session_code)
-                    .arg("get")
-                    .arg("item")
-                    .arg(item)
-                    .arg(format!("--fields={}", fields.join(",")))
-                    .arg("--format=CSV")
-                    .output()?;
-                let s = String::from_utf8(out.stdout)?;
-                Ok(s.trim()
-                    .split(',')
-                    .map(|s| s.to_string())
-                    .collect::<Vec<_>>())
-            }
-            SessionCode::V2KeyValuePair { key, value } => {
-                let out = Command::new(&self.bin_filename)
-                    .env(key, value)
-                    .arg("item")
-                    .arg("get")
-                    .arg(item)
-                    .arg(format!("--fields={}", fields.join(",")))
-                    .output()?;
-                let s = String::from_utf8(out.stdout)?;
-                Ok(s.trim()
-                    .split(',')
-                    .map(|s| s.to_string())
-                    .collect::<Vec<_>>())
+            SessionCode::V1PlainString(session_code) => (
+                format!("OP_SESSION_{}", self.shorthand),
+                session_code.clone(),
+            ),
+            SessionCode::V2KeyValuePair { key, value } => (key.clone(), value.clone()),
+            SessionCode::ServiceAccount(token) => {
+                (SERVICE_ACCOUNT_TOKEN_ENV.to_string(), token.clone())
             }
         }
     }
+
+    /// op with the session in its env; a token inherited from the parent would take
+    /// precedence over a signed in session, so it is removed then
+    pub(crate) fn command(&self) -> Command {
+        let (key, value) = self.env();
+        let mut cmd = Command::new(&self.bin_filename);
+        if !matches!(self.session_code, SessionCode::ServiceAccount(_)) {
+            cmd.env_remove(SERVICE_ACCOUNT_TOKEN_ENV);
+        }
+        cmd.env(key, value);
+        cmd
+    }
+
+    /// `op get item` (1.x) or `op item get --format json` (2.x), both printing json
+    pub(crate) fn get_item_command(&self, item: &str) -> Command {
+        let mut cmd = self.command();
+        match &self.major_version {
+            ReleaseNoteUrl::V1 => cmd.arg("get").arg("item").arg(item),
+            ReleaseNoteUrl::V2 => cmd
+                .arg("item")
+                .arg("get")
+                .arg(item)
+                .arg("--format")
+                .arg("json"),
+        };
+        cmd
+    }
+
+    pub(crate) fn parse_item(&self, out: Output) -> Result<Item, OpError> {
+        OpError::check(&out, OpAction::Other)?;
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        Item::from_json(&self.major_version, &stdout)
+            .map_err(|e| OpError::UnexpectedOutput(e.to_string()))
+    }
+
+    /// the item by name, id or link
+    pub async fn get_item(&self, item: &str) -> Result<Item, OpError> {
+        let out = tokio::process::Command::from(self.get_item_command(item))
+            .output()
+            .await
+            .map_err(|e| OpError::from_spawn(&self.bin_filename, e))?;
+        self.parse_item(out)
+    }
+
+    /// the values of the fields (by label or id) of the item, in the order given
+    pub async fn item_fields(&self, item: &str, fields: &[&str]) -> Result<Vec<String>, OpError> {
+        self.get_item(item).await?.field_values(fields)
+    }
 }
 
 impl Account {
@@ -159,4 +222,87 @@ Accounts on this device:
         let accounts = Account::from_descriptions(desc);
         assert_eq!(3, accounts.len());
     }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_item_fields() {
+        use crate::session::error::OpError;
+        use crate::session::signin::test::fake_op;
+        use crate::session::types::{Session, SessionCode};
+        use crate::ReleaseNoteUrl;
+
+        // prints the item only with the session and the json format; no other item exists
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/items/item_v2.json");
+        let op = fake_op(
+            "session_item_fields",
+            &format!(
+                concat!(
+                    "[ \"$OP_SESSION_my,$1,$2,$5\" = 'abc,item,get,json' ] || exit 2\n",
+                    "[ \"$3\" = doom ] || {{ echo \"[ERROR] 2022/01/08 02:51:50 \\\"$3\\\" isn't an item.\" >&2; exit 1; }}\n",
+                    "cat {}"
+                ),
+                fixture
+            ),
+        );
+        let session = Session {
+            bin_filename: op.to_string_lossy().into_owned(),
+            shorthand: "my".to_string(),
+            session_code: SessionCode::V2KeyValuePair {
+                key: "OP_SESSION_my".to_string(),
+                value: "abc".to_string(),
+            },
+            major_version: ReleaseNoteUrl::V2,
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let fut = session.item_fields("doom", &["username", "password"]);
+        assert_eq!(vec!["doomguy", "iddqd,idkfa"], rt.block_on(fut).unwrap());
+        let err = rt.block_on(session.get_item("quake")).unwrap_err();
+        assert_eq!(OpError::ItemNotFound("quake".to_string()), err);
+        let err = rt
+            .block_on(session.item_fields("doom", &["username", "bfg"]))
+            .unwrap_err();
+        assert!(matches!(err, OpError::FieldNotFound { .. }));
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_service_account_session() {
+        use crate::session::signin::test::fake_op;
+        use crate::session::types::{Session, SERVICE_ACCOUNT_TOKEN_ENV};
+        use crate::{LocalVersion, Platform};
+        use semver::Version;
+
+        // prints the item only with the token
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/items/item_v2.json");
+        let op = fake_op(
+            "session_service_account",
+            &format!(
+                "[ \"${}\" = ops_abc ] || exit 1\ncat {}",
+                SERVICE_ACCOUNT_TOKEN_ENV, fixture
+            ),
+        );
+        let lv = |version: Version| LocalVersion {
+            version,
+            platform: Platform::current(),
+            path: op.to_string_lossy().into_owned(),
+            manifest: None,
+        };
+        assert!(
+            Session::from_service_account_token(&lv(Version::new(1, 12, 3)), "ops_abc").is_err()
+        );
+        assert!(
+            Session::from_service_account_token(&lv(Version::new(0, 9, 4)), "ops_abc").is_err()
+        );
+        let session =
+            Session::from_service_account_token(&lv(Version::new(2, 1, 0)), "ops_abc").unwrap();
+        assert_eq!(
+            (SERVICE_ACCOUNT_TOKEN_ENV.to_string(), "ops_abc".to_string()),
+            session.env()
+        );
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let fut = session.item_fields("doom", &["username"]);
+        assert_eq!(vec!["doomguy"], rt.block_on(fut).unwrap());
+        std::fs::remove_dir_all(op.parent().unwrap()).unwrap();
+    }
 }